@@ -0,0 +1,268 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::error::Error;
+use crate::keymap::Keymap;
+use crate::output::Output;
+use crate::step::Process;
+use crate::supervisor::Termination;
+use crate::utils::{escape_double_quoted, escape_single_quoted, shell_quote};
+
+// What a secret prompt's input is replaced with in the log
+pub const REDACTED: &str = "<redacted>";
+
+// `cmd` with `secret` replaced by `REDACTED`, however it was quoted when it
+// was put in (see `template::render`)
+pub fn redact(cmd: &str, secret: &str) -> String {
+    if secret.is_empty() {
+        return cmd.to_owned();
+    }
+
+    let mut forms = [
+        shell_quote(secret),
+        escape_single_quoted(secret),
+        escape_double_quoted(secret),
+        secret.to_owned(),
+    ];
+
+    // So a quoted form is replaced as a whole, quotes included
+    forms.sort_by_key(|form| Reverse(form.len()));
+    forms.iter().fold(cmd.to_owned(), |cmd, form| {
+        cmd.replace(form.as_str(), REDACTED)
+    })
+}
+
+// One executed command, as a line of the log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    pub cmd: String,
+    pub key: char,
+    pub description: String,
+    pub cwd: PathBuf,
+    pub start: DateTime<Local>,
+    pub end: DateTime<Local>,
+    pub termination: Termination,
+    pub exit_code: i32,
+    // SHA-256 of everything the command wrote, both streams combined
+    pub output_sha256: String,
+    // What was typed at the prompt, unless it was secret (or there was no
+    // prompt, or the log was written before this was recorded)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input: Option<String>,
+}
+
+impl Record {
+    // `cmd` is the command as it was run, or with the secret input replaced
+    // by `REDACTED` when the keymap's prompt is secret
+    pub fn new(
+        keymap: &Keymap,
+        cmd: &str,
+        input: Option<&str>,
+        process: &Process,
+        start: DateTime<Local>,
+    ) -> Result<Self, Error> {
+        let termination = match process {
+            Process::Cancelled(_, signal) => Termination::Cancelled(*signal),
+            Process::TimedOut(_) => Termination::TimedOut,
+            Process::Output(_) | Process::Exit => Termination::Exited,
+        };
+        let output_sha256 = output_sha256(process.output())?;
+
+        Ok(Self {
+            cmd: cmd.to_owned(),
+            key: keymap.key,
+            description: keymap.description.clone(),
+            cwd: env::current_dir()?,
+            start,
+            end: Local::now(),
+            termination,
+            exit_code: process.exit_code(),
+            output_sha256,
+            input: input.filter(|_| !keymap.secret).map(str::to_owned),
+        })
+    }
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self.termination {
+            Termination::Exited => format!("exit {}", self.exit_code),
+            Termination::Cancelled(_) => "cancelled".to_owned(),
+            Termination::TimedOut => "timed out".to_owned(),
+        };
+
+        write!(
+            f,
+            "{}  {}  {:<9}  {}",
+            self.start.format("%Y-%m-%d %H:%M:%S"),
+            self.key,
+            status,
+            self.cmd
+        )
+    }
+}
+
+// A spilled output is hashed from its file, which has all of it
+fn output_sha256(output: Option<&Output>) -> Result<String, Error> {
+    let mut hasher = Sha256::new();
+
+    match output.and_then(|output| output.spill_file.as_ref()) {
+        Some(path) => {
+            let mut file = File::open(path)?;
+            let mut buf = [0; 8192];
+
+            loop {
+                match file.read(&mut buf)? {
+                    0 => break,
+                    len => hasher.update(&buf[..len]),
+                }
+            }
+        }
+        None => {
+            if let Some(output) = output {
+                hasher.update(output.combined_bytes());
+            }
+        }
+    }
+
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+// How often and how recently each keymap was run, for `Order::Frecency`.
+// Each run counts less as it gets older. Runs are matched to keymaps by key
+// and description, since the same key can mean different things in
+// different projects.
+#[derive(Debug, Default, Clone)]
+pub struct Frecency {
+    scores: HashMap<(char, String), f64>,
+}
+
+impl Frecency {
+    // The records are gone through once, however many keymaps are scored
+    pub fn new(records: &[Record], now: DateTime<Local>) -> Self {
+        let mut scores = HashMap::new();
+
+        for record in records {
+            let score = match (now - record.start).num_days() {
+                ..4 => 100.0,
+                4..14 => 70.0,
+                14..31 => 50.0,
+                31..90 => 30.0,
+                _ => 10.0,
+            };
+
+            *scores
+                .entry((record.key, record.description.clone()))
+                .or_insert(0.0) += score;
+        }
+
+        Self { scores }
+    }
+
+    pub fn score(&self, keymap: &Keymap) -> f64 {
+        self.scores
+            .get(&(keymap.key, keymap.description.clone()))
+            .copied()
+            .unwrap_or(0.0)
+    }
+}
+
+// Which records `quicommand log` shows
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Filter {
+    pub key: Option<char>,
+    // Part of the command or description
+    pub text: Option<String>,
+    pub failed: bool,
+    // Only the last `limit` matching records
+    pub limit: Option<usize>,
+}
+
+impl Filter {
+    pub fn matches(&self, record: &Record) -> bool {
+        self.key.is_none_or(|key| key == record.key)
+            && self.text.as_ref().is_none_or(|text| {
+                record.cmd.contains(text.as_str()) || record.description.contains(text.as_str())
+            })
+            && (!self.failed || record.exit_code != 0)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    pub path: PathBuf,
+}
+
+impl AuditLog {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_owned(),
+        }
+    }
+
+    // `$XDG_STATE_HOME/quicommand/log.jsonl`, which is usually
+    // `~/.local/state/quicommand/log.jsonl`
+    pub fn default_path() -> Option<PathBuf> {
+        let state_home = match env::var_os("XDG_STATE_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".local/state"),
+        };
+
+        Some(state_home.join("quicommand").join("log.jsonl"))
+    }
+
+    pub fn append(&self, record: &Record) -> Result<(), Error> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut line = serde_json::to_string(record).map_err(io::Error::from)?;
+
+        line.push('\n');
+        // A single write, so concurrent sessions don't interleave lines
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?
+            .write_all(line.as_bytes())?;
+
+        Ok(())
+    }
+
+    // Lines that can't be parsed (e.g. cut short by a crash) are skipped
+    pub fn records(&self, filter: &Filter) -> Result<Vec<Record>, Error> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut records = Vec::new();
+
+        for line in BufReader::new(file).lines() {
+            if let Ok(record) = serde_json::from_str::<Record>(&line?) {
+                if filter.matches(&record) {
+                    records.push(record);
+                }
+            }
+        }
+
+        if let Some(limit) = filter.limit {
+            records.drain(..records.len().saturating_sub(limit));
+        }
+
+        Ok(records)
+    }
+}
@@ -0,0 +1,324 @@
+use std::os::fd::RawFd;
+use std::str::FromStr;
+
+use serde_json::{json, Value};
+
+use crate::audit::Filter;
+use crate::error::Error;
+use crate::keymap::Keymap;
+use crate::shell::Syntax;
+
+// The version of the `list --format json` schema, bumped whenever a field
+// changes meaning or goes away (adding fields doesn't count)
+pub const LIST_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum ListFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for ListFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(ListFormat::Text),
+            "json" => Ok(ListFormat::Json),
+            _ => Err(Error::Usage(format!(
+                "`{}` isn't one of `text` or `json`",
+                s
+            ))),
+        }
+    }
+}
+
+// Where `--print` writes the chosen command, and the shell it's written for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Print {
+    pub fd: RawFd,
+    pub syntax: Syntax,
+}
+
+// What `quicommand` was asked to do on the command line
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    // `quicommand [--pager] [--edit] [--dry-run]
+    //     [--print | --print-fd N] [--print-shell posix|fish]`
+    Palette {
+        pager: bool,
+        // Every command goes through the line editor before it's run
+        edit: bool,
+        dry_run: bool,
+        // Where the chosen command is written instead of being run
+        print: Option<Print>,
+    },
+    // `quicommand run <key or name> [--<prompt name> <value>]`
+    Run {
+        target: String,
+        args: Vec<(String, String)>,
+    },
+    // `quicommand list [--format text|json]`
+    List {
+        format: ListFormat,
+    },
+    // `quicommand shell-init <bash|zsh|fish>`
+    ShellInit {
+        shell: String,
+    },
+    // `quicommand stats`
+    Stats,
+    // `quicommand log [--key KEY] [--grep TEXT] [--failed] [--limit N] [--json]`
+    Log {
+        filter: Filter,
+        json: bool,
+    },
+}
+
+// `args` doesn't include the program's name
+pub fn parse(args: &[String]) -> Result<Command, Error> {
+    match args.first().map(String::as_str) {
+        Some("run") => parse_run(&args[1..]),
+        Some("list") => parse_list(&args[1..]),
+        Some("log") => parse_log(&args[1..]),
+        Some("stats") => match &args[1..] {
+            [] => Ok(Command::Stats),
+            [arg, ..] => Err(unknown_argument(arg)),
+        },
+        Some("shell-init") => match &args[1..] {
+            [shell] => Ok(Command::ShellInit {
+                shell: shell.clone(),
+            }),
+            _ => Err(Error::Usage(
+                "`shell-init` needs one of `bash`, `zsh` or `fish`".to_owned(),
+            )),
+        },
+        _ => parse_palette(args),
+    }
+}
+
+// The input for a keymap's prompt, which is given as `--<prompt name>`
+pub fn keymap_input(keymap: &Keymap, args: &[(String, String)]) -> Result<Option<String>, Error> {
+    let prompt_name = keymap.prompt_name();
+
+    if let Some((name, _)) = args
+        .iter()
+        .find(|(name, _)| keymap.prompt.is_none() || name != prompt_name)
+    {
+        return Err(unknown_argument(&format!("--{}", name)));
+    }
+
+    match (&keymap.prompt, args.last()) {
+        (None, _) => Ok(None),
+        (Some(_), Some((_, value))) => Ok(Some(value.clone())),
+        (Some(_), None) => Err(Error::Usage(format!(
+            "`{}` needs --{}",
+            keymap
+                .name
+                .clone()
+                .unwrap_or_else(|| keymap.key.to_string()),
+            prompt_name
+        ))),
+    }
+}
+
+// What `list --format json` prints:
+//
+//     {
+//       "version": 1,
+//       "keymaps": [
+//         {
+//           "key": "c",
+//           "name": "commit",          // or null
+//           "description": "Git commit",
+//           "cmd": "git commit -m {message}",
+//           "prompts": [               // zero or one entries
+//             {
+//               "name": "message",
+//               "message": "Commit message:",
+//               "env_var": "QC_MESSAGE",
+//               "pass_input": "cmd",   // or "env" or "stdin"
+//               "secret": false
+//             }
+//           ],
+//           "source": "/home/alex/project/.quicommand.toml", // null if built in
+//           "conditions": ["exists:.git"],
+//           "available": true          // whether the conditions are met
+//         }
+//       ]
+//     }
+//
+// A keymap has at most one prompt, so `prompts` is empty or has a single
+// entry. It's a list so that keymaps with several prompts won't need a new
+// version.
+pub fn keymaps_json(keymaps: &[Keymap]) -> Value {
+    let keymaps: Vec<Value> = keymaps
+        .iter()
+        .map(|keymap| {
+            let prompts: Vec<Value> = keymap
+                .prompt
+                .iter()
+                .map(|message| {
+                    json!({
+                        "name": keymap.prompt_name(),
+                        "message": message,
+                        "env_var": keymap.prompt_env_var(),
+                        "pass_input": keymap.pass_input.to_string(),
+                        "secret": keymap.secret,
+                    })
+                })
+                .collect();
+            let conditions: Vec<String> = keymap
+                .conditions
+                .iter()
+                .map(|condition| condition.to_string())
+                .collect();
+
+            json!({
+                "key": keymap.key.to_string(),
+                "name": keymap.name,
+                "description": keymap.description,
+                "cmd": keymap.cmd,
+                "prompts": prompts,
+                "source": keymap.source,
+                "conditions": conditions,
+                "available": keymap.is_available(),
+            })
+        })
+        .collect();
+
+    json!({
+        "version": LIST_SCHEMA_VERSION,
+        "keymaps": keymaps,
+    })
+}
+
+fn parse_palette(args: &[String]) -> Result<Command, Error> {
+    let mut pager = false;
+    let mut edit = false;
+    let mut dry_run = false;
+    let mut print_fd = None;
+    let mut syntax = None;
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--pager" => pager = true,
+            "--edit" => edit = true,
+            "--dry-run" => dry_run = true,
+            "--print" => print_fd = Some(libc::STDOUT_FILENO),
+            "--print-fd" => {
+                let fd = value(&mut args, arg)?;
+
+                print_fd = Some(
+                    fd.parse()
+                        .map_err(|_| Error::Usage(format!("`{}` isn't a file descriptor", fd)))?,
+                );
+            }
+            "--print-shell" => syntax = Some(value(&mut args, arg)?.parse()?),
+            _ => return Err(unknown_argument(arg)),
+        }
+    }
+
+    if syntax.is_some() && print_fd.is_none() {
+        return Err(Error::Usage(
+            "`--print-shell` needs `--print` or `--print-fd`".to_owned(),
+        ));
+    }
+
+    Ok(Command::Palette {
+        pager,
+        edit,
+        dry_run,
+        print: print_fd.map(|fd| Print {
+            fd,
+            syntax: syntax.unwrap_or_default(),
+        }),
+    })
+}
+
+fn parse_run(args: &[String]) -> Result<Command, Error> {
+    let mut args = args.iter();
+    let target = args
+        .next()
+        .ok_or_else(|| Error::Usage("`run` needs a key or name".to_owned()))?
+        .clone();
+    let mut values = Vec::new();
+
+    while let Some(arg) = args.next() {
+        let Some(name) = arg.strip_prefix("--") else {
+            return Err(unknown_argument(arg));
+        };
+        let (name, value) = match name.split_once('=') {
+            Some((name, value)) => (name, value),
+            None => (name, value(&mut args, arg)?),
+        };
+
+        values.push((name.to_owned(), value.to_owned()));
+    }
+
+    Ok(Command::Run {
+        target,
+        args: values,
+    })
+}
+
+fn parse_list(args: &[String]) -> Result<Command, Error> {
+    let mut format = ListFormat::default();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => format = value(&mut args, arg)?.parse()?,
+            _ => return Err(unknown_argument(arg)),
+        }
+    }
+
+    Ok(Command::List { format })
+}
+
+fn parse_log(args: &[String]) -> Result<Command, Error> {
+    let mut filter = Filter::default();
+    let mut json = false;
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--key" => {
+                let key = value(&mut args, arg)?;
+                let mut chars = key.chars();
+
+                match (chars.next(), chars.next()) {
+                    (Some(key), None) => filter.key = Some(key),
+                    _ => return Err(Error::Usage(format!("`{}` isn't a single key", key))),
+                }
+            }
+            "--grep" => filter.text = Some(value(&mut args, arg)?.to_owned()),
+            "--failed" => filter.failed = true,
+            "--limit" => {
+                let limit = value(&mut args, arg)?;
+
+                filter.limit = Some(
+                    limit
+                        .parse()
+                        .map_err(|_| Error::Usage(format!("`{}` isn't a number", limit)))?,
+                );
+            }
+            "--json" => json = true,
+            _ => return Err(unknown_argument(arg)),
+        }
+    }
+
+    Ok(Command::Log { filter, json })
+}
+
+fn value<'a>(args: &mut impl Iterator<Item = &'a String>, arg: &str) -> Result<&'a str, Error> {
+    args.next()
+        .map(String::as_str)
+        .ok_or_else(|| Error::Usage(format!("`{}` needs a value", arg)))
+}
+
+fn unknown_argument(arg: &str) -> Error {
+    Error::Usage(format!("unknown argument `{}`", arg))
+}
@@ -1,92 +1,585 @@
 use std::{
-    io::{self, stderr, stdout, BufRead, BufReader, Write},
-    process::{Command, Output, Stdio},
-    str::FromStr,
-    thread::JoinHandle,
+    env,
+    fs::File,
+    io::{self, stderr, stdout, Read, Write},
+    os::{fd::AsRawFd, unix::process::CommandExt},
+    process::{Child, Command, Stdio},
+    sync::mpsc::{self, Receiver, Sender},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
-use crate::utils::starts_with_any;
+use signal_hook::{
+    consts::SIGWINCH,
+    iterator::{Handle, Signals},
+};
+
+use crate::error::Error;
+use crate::output::{
+    Chunk, Output, OutputBuffer, OutputEvent, Retention, Stream, DEFAULT_MEMORY_CAP,
+};
+use crate::pty::{self, Pty};
+use crate::shell::Shell;
+use crate::supervisor::{Supervisor, Termination, Watch};
+use crate::utils::{shell_quote, split_words};
 
+// Programs that take over the terminal, matched against the start of a
+// command. An entry can span several words (e.g. `git rebase -i`).
+pub const INTERACTIVE_PROGRAMS: &[&str] = &[
+    "hx",
+    "vi",
+    "vim",
+    "nvim",
+    "nano",
+    "emacs",
+    "less",
+    "more",
+    "man",
+    "top",
+    "htop",
+    "fzf",
+    "git rebase -i",
+    "git add -p",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum CmdType {
     Interactive,
     Script,
 }
 
+// How a script's output reaches us: through pseudo-terminals, so programs
+// keep their colors and progress bars, or through plain pipes
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum OutputMode {
+    #[default]
+    Pty,
+    Pipe,
+}
+
 pub struct CmdRunner {
+    pub cmd_str: String,
+    pub shell: Shell,
     pub cmd: Command,
     pub cmd_type: CmdType,
+    pub output_mode: OutputMode,
+    pub envs: Vec<(String, String)>,
+    pub stdin: Option<Vec<u8>>,
+    pub timeout: Option<Duration>,
+    // How many bytes of output `run_with_output` keeps in memory
+    pub memory_cap: usize,
+    pub retention: Retention,
+    // How the last run ended
+    pub termination: Termination,
 }
 
-impl FromStr for CmdType {
-    type Err = ();
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if starts_with_any(s, &["hx", "vi", "fzf"]) {
-            Ok(CmdType::Interactive)
+impl From<bool> for CmdType {
+    fn from(interactive: bool) -> Self {
+        if interactive {
+            CmdType::Interactive
         } else {
-            Ok(CmdType::Script)
+            CmdType::Script
+        }
+    }
+}
+
+impl CmdType {
+    pub fn detect<S: AsRef<str>>(cmd_str: &str, interactive_programs: &[S]) -> Self {
+        let mut words = split_words(cmd_str);
+        // Leading assignments like `EDITOR=vim` aren't the program
+        let program_start = words.iter().take_while(|w| is_assignment(w)).count();
+
+        words.drain(..program_start);
+
+        if let Some(program) = words.first_mut() {
+            if let Some((_, name)) = program.rsplit_once('/') {
+                *program = name.to_owned();
+            }
+        }
+
+        let is_interactive = interactive_programs.iter().any(|program| {
+            let program_words = split_words(program.as_ref());
+
+            !program_words.is_empty() && words.starts_with(&program_words)
+        });
+
+        CmdType::from(is_interactive)
+    }
+}
+
+fn is_assignment(word: &str) -> bool {
+    match word.split_once('=') {
+        Some((name, _)) => {
+            !name.is_empty()
+                && !name.starts_with(|c: char| c.is_ascii_digit())
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
         }
+        None => false,
     }
 }
 
-fn tee<R, W>(reader: R, mut writer: W) -> JoinHandle<io::Result<String>>
+// The variables `cmd_str` expands (`$NAME` or `${NAME}`), each once. Single
+// quotes and backslashes are honored, so `'$HOME'` and `\$HOME` don't count.
+fn referenced_vars(cmd_str: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    let mut chars = cmd_str.chars().peekable();
+    let mut single_quoted = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => single_quoted = !single_quoted,
+            '\\' if !single_quoted => {
+                chars.next();
+            }
+            '$' if !single_quoted => {
+                // `${NAME}`, or `${NAME:-default}` and the like
+                chars.next_if_eq(&'{');
+
+                let mut name = String::new();
+
+                while let Some(c) = chars.next_if(|&c| c == '_' || c.is_ascii_alphanumeric()) {
+                    name.push(c);
+                }
+
+                let is_name = !name.is_empty() && !name.starts_with(|c: char| c.is_ascii_digit());
+
+                if is_name && !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    names
+}
+
+// This function sends what it reads as events as soon as it arrives, so
+// progress bars and prompts without a trailing newline show up right away
+fn read_events<R>(
+    mut reader: R,
+    to_event: fn(Vec<u8>) -> OutputEvent,
+    events: Sender<OutputEvent>,
+) -> JoinHandle<io::Result<()>>
 where
-    R: BufRead + Send + 'static,
-    W: Write + Send + 'static,
+    R: Read + Send + 'static,
 {
-    std::thread::spawn(move || {
-        let mut capture = String::new();
+    thread::spawn(move || {
+        let mut buf = [0; 4096];
 
-        for line in reader.lines() {
-            let line = line?;
+        loop {
+            let len = match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(len) => len,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) if pty::is_eof(&e) => break,
+                Err(e) => return Err(e),
+            };
 
-            capture.push_str(&line);
-            writer.write_all(line.as_bytes())?;
-            writer.write_all(b"\r\n")?;
-            writer.flush()?;
+            // Nobody listening anymore isn't a reason to stop draining
+            events.send(to_event(buf[..len].to_vec())).ok();
         }
 
-        Ok(capture)
+        Ok(())
     })
 }
 
+// Keeps the pseudo-terminals' window size in sync with ours. Dropping it
+// stops the thread too, e.g. when the command couldn't be spawned.
+struct Resize {
+    handle: Handle,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Resize {
+    fn start(masters: [File; 2]) -> io::Result<Self> {
+        let mut signals = Signals::new([SIGWINCH])?;
+        let handle = signals.handle();
+        let thread = thread::spawn(move || {
+            for _ in signals.forever() {
+                let size = pty::window_size();
+
+                for master in &masters {
+                    pty::set_window_size(master.as_raw_fd(), size).ok();
+                }
+            }
+        });
+
+        Ok(Self {
+            handle,
+            thread: Some(thread),
+        })
+    }
+
+    fn stop(mut self) -> Result<(), Error> {
+        self.handle.close();
+
+        match self.thread.take() {
+            Some(thread) => thread.join().map_err(|_| Error::thread_panicked("resize")),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Resize {
+    fn drop(&mut self) {
+        self.handle.close();
+
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+// The events of a running command, ending with `OutputEvent::Exit`
+pub struct OutputStream {
+    events: Receiver<OutputEvent>,
+    waiter: JoinHandle<Result<Termination, Error>>,
+}
+
+impl Iterator for OutputStream {
+    type Item = OutputEvent;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.events.recv().ok()
+    }
+}
+
+impl OutputStream {
+    fn new(
+        mut child: Child,
+        watch: Watch,
+        stdout: impl Read + Send + 'static,
+        stderr: impl Read + Send + 'static,
+        resize: Option<Resize>,
+    ) -> Self {
+        let (sender, events) = mpsc::channel();
+        let stdout_thread = read_events(stdout, OutputEvent::Stdout, sender.clone());
+        let stderr_thread = read_events(stderr, OutputEvent::Stderr, sender.clone());
+        let waiter = thread::spawn(move || {
+            let stdout_result = joined(stdout_thread, "stdout");
+            let stderr_result = joined(stderr_thread, "stderr");
+            let exit_status = child.wait();
+            let termination = watch.finish();
+
+            if let Some(resize) = resize {
+                resize.stop()?;
+            }
+
+            stdout_result?;
+            stderr_result?;
+            sender.send(OutputEvent::Exit(exit_status?)).ok();
+
+            Ok(termination)
+        });
+
+        Self { events, waiter }
+    }
+
+    // This waits for the command to finish (skipping any events left) and
+    // tells how it ended
+    pub fn finish(self) -> Result<Termination, Error> {
+        drop(self.events);
+
+        self.waiter
+            .join()
+            .map_err(|_| Error::thread_panicked("wait"))?
+    }
+}
+
+fn to_crlf(chunk: &[u8], mut last_byte: u8) -> Vec<u8> {
+    let mut result = Vec::with_capacity(chunk.len());
+
+    for &byte in chunk {
+        if byte == b'\n' && last_byte != b'\r' {
+            result.push(b'\r');
+        }
+        result.push(byte);
+        last_byte = byte;
+    }
+
+    result
+}
+
+fn write_flushed(writer: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    writer.write_all(bytes)?;
+    writer.flush()
+}
+
+fn joined<T>(thread: JoinHandle<io::Result<T>>, name: &str) -> Result<T, Error> {
+    let result = thread.join().map_err(|_| Error::thread_panicked(name))?;
+
+    Ok(result?)
+}
+
 impl CmdRunner {
     pub fn new(cmd_str: &str) -> CmdRunner {
-        let mut cmd = Command::new("script");
+        let cmd_type = CmdType::detect(cmd_str, INTERACTIVE_PROGRAMS);
+
+        CmdRunner::with_cmd_type(cmd_str, cmd_type)
+    }
+
+    pub fn with_cmd_type(cmd_str: &str, cmd_type: CmdType) -> CmdRunner {
+        let shell = Shell::default();
+        let cmd = shell.command(cmd_str);
+
+        CmdRunner {
+            cmd_str: cmd_str.to_owned(),
+            shell,
+            cmd,
+            cmd_type,
+            output_mode: OutputMode::default(),
+            envs: Vec::new(),
+            stdin: None,
+            timeout: None,
+            memory_cap: DEFAULT_MEMORY_CAP,
+            retention: Retention::default(),
+            termination: Termination::Exited,
+        }
+    }
+
+    // This rebuilds `cmd`, so call it before configuring the command further
+    pub fn with_shell(mut self, shell: Shell) -> Self {
+        self.cmd = shell.command(&self.cmd_str);
+        self.shell = shell;
+        self
+    }
+
+    pub fn with_env<K: AsRef<str>, V: AsRef<str>>(mut self, key: K, value: V) -> Self {
+        self.envs
+            .push((key.as_ref().to_owned(), value.as_ref().to_owned()));
+        self
+    }
 
-        cmd.arg("-qec").arg(cmd_str).arg("/dev/null");
+    // Data written to the command's stdin, which is closed afterwards
+    pub fn with_stdin<D: Into<Vec<u8>>>(mut self, data: D) -> Self {
+        self.stdin = Some(data.into());
+        self
+    }
 
-        let cmd_type = CmdType::from_str(cmd_str).unwrap();
+    // The command (and everything it started) is stopped after `timeout`
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
 
-        CmdRunner { cmd, cmd_type }
+    // Output past `memory_cap` bytes is spilled to a file, see `OutputBuffer`
+    pub fn with_memory_cap(mut self, memory_cap: usize) -> Self {
+        self.memory_cap = memory_cap;
+        self
     }
 
-    pub fn run(&mut self) -> Result<Output, std::io::Error> {
-        let child = self.cmd.spawn().expect("failed to spawn command");
-        let output = child.wait_with_output()?;
+    pub fn with_retention(mut self, retention: Retention) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    // The program and arguments that will be spawned
+    pub fn argv(&self) -> Vec<String> {
+        self.shell.argv(&self.cmd_str)
+    }
 
-        Ok(output)
+    pub fn with_output_mode(mut self, output_mode: OutputMode) -> Self {
+        self.output_mode = output_mode;
+        self
     }
 
-    pub fn run_with_output(&mut self) -> Result<Output, std::io::Error> {
+    // What would be spawned, one item per line, for `--dry-run`. Arguments
+    // are quoted for a POSIX shell, so quoting problems show up. The
+    // environment is inherited, so besides what's added, only the variables
+    // the command refers to are listed.
+    pub fn describe(&self) -> io::Result<String> {
+        let mut lines: Vec<String> = self
+            .argv()
+            .iter()
+            .enumerate()
+            .map(|(i, arg)| format!("argv[{}]: {}", i, shell_quote(arg)))
+            .collect();
+
+        lines.push(format!("cwd: {}", env::current_dir()?.display()));
+        lines.push("env: inherited".to_owned());
+        lines.extend(
+            self.envs
+                .iter()
+                .map(|(key, value)| format!("env: {}={}", key, shell_quote(value))),
+        );
+        // Of everything inherited, only what the command uses
+        lines.extend(
+            referenced_vars(&self.cmd_str)
+                .into_iter()
+                .filter(|name| !self.envs.iter().any(|(key, _)| key == name))
+                .map(|name| match env::var(&name) {
+                    Ok(value) => format!("env: {}={} (inherited)", name, shell_quote(&value)),
+                    Err(_) => format!("env: {} (unset)", name),
+                }),
+        );
+
+        if let Some(stdin) = &self.stdin {
+            lines.push(format!("stdin: {:?}", String::from_utf8_lossy(stdin)));
+        }
+
+        lines.push(match (self.cmd_type, self.output_mode) {
+            (CmdType::Interactive, _) => "output: terminal".to_owned(),
+            (CmdType::Script, OutputMode::Pty) => "output: captured (pty)".to_owned(),
+            (CmdType::Script, OutputMode::Pipe) => "output: captured (pipe)".to_owned(),
+        });
+
+        if let Some(timeout) = self.timeout {
+            lines.push(format!("timeout: {:?}", timeout));
+        }
+
+        Ok(lines.join("\n"))
+    }
+
+    fn spawn(&mut self) -> Result<Child, Error> {
+        self.cmd.envs(self.envs.iter().map(|(k, v)| (k, v)));
+
+        if self.stdin.is_some() {
+            self.cmd.stdin(Stdio::piped());
+        }
+
+        let mut child = self.cmd.spawn().map_err(|source| Error::Spawn {
+            cmd: self.cmd_str.clone(),
+            source,
+        })?;
+
+        if let (Some(data), Some(mut pipe)) = (self.stdin.clone(), child.stdin.take()) {
+            // The command may exit without reading it all, so errors are ignored
+            thread::spawn(move || pipe.write_all(&data));
+        }
+
+        Ok(child)
+    }
+
+    // Interactive commands talk to our terminal directly
+    pub fn run(&mut self) -> Result<Output, Error> {
+        let supervisor = Supervisor::new(false, self.timeout)?;
+        let child = self.spawn()?;
+        let watch = supervisor.watch(child.id() as libc::pid_t);
+        let output = child.wait_with_output();
+
+        self.termination = watch.finish();
+
+        Ok(output?.into())
+    }
+
+    // The output is echoed to our terminal as it arrives and returned once
+    // the command is done
+    pub fn run_with_output(&mut self) -> Result<Output, Error> {
+        let start = Instant::now();
+        let mut stream = self.stream()?;
+        let mut buffer = OutputBuffer::new(self.memory_cap, self.retention);
+        let mut exit_status = None;
+        let mut last_bytes = [0; 2];
+        let raw = [libc::STDOUT_FILENO, libc::STDERR_FILENO].map(pty::is_raw);
+
+        for event in stream.by_ref() {
+            let (stream, bytes) = match event {
+                OutputEvent::Stdout(bytes) => (Stream::Stdout, bytes),
+                OutputEvent::Stderr(bytes) => (Stream::Stderr, bytes),
+                OutputEvent::Exit(status) => {
+                    exit_status = Some(status);
+                    continue;
+                }
+            };
+            let last_byte = &mut last_bytes[stream as usize];
+            // In raw mode, a bare `\n` won't return the cursor
+            let echo = if raw[stream as usize] {
+                to_crlf(&bytes, *last_byte)
+            } else {
+                bytes.clone()
+            };
+
+            match stream {
+                Stream::Stdout => write_flushed(&mut stdout(), &echo)?,
+                Stream::Stderr => write_flushed(&mut stderr(), &echo)?,
+            }
+
+            *last_byte = bytes[bytes.len() - 1];
+            buffer.push(Chunk {
+                stream,
+                at: start.elapsed(),
+                bytes,
+            })?;
+        }
+
+        self.termination = stream.finish()?;
+
+        let exit_status =
+            exit_status.ok_or_else(|| io::Error::other("command ended without a status"))?;
+
+        Ok(buffer.finish(exit_status)?)
+    }
+
+    // This starts the command and returns its output as events instead of
+    // echoing it, for callers that want to handle it themselves
+    pub fn stream(&mut self) -> Result<OutputStream, Error> {
         // This prevents the output from becoming messed up in tests.
         self.cmd.stdin(Stdio::null());
+
+        match self.output_mode {
+            OutputMode::Pty => self.stream_from_pty(),
+            OutputMode::Pipe => self.stream_from_pipes(),
+        }
+    }
+
+    fn stream_from_pty(&mut self) -> Result<OutputStream, Error> {
+        let size = pty::window_size();
+        // Separate terminals keep stdout and stderr apart
+        let stdout_pty = Pty::open(size)?;
+        let stderr_pty = Pty::open(size)?;
+        let masters = [
+            stdout_pty.master.try_clone()?,
+            stderr_pty.master.try_clone()?,
+        ];
+
+        self.cmd.stdout(stdout_pty.slave);
+        self.cmd.stderr(stderr_pty.slave);
+
+        unsafe {
+            self.cmd
+                .pre_exec(|| pty::make_controlling_terminal(libc::STDOUT_FILENO));
+        }
+
+        let resize = Resize::start(masters)?;
+        let supervisor = Supervisor::new(true, self.timeout)?;
+        let child = self.spawn();
+
+        // The masters only hit EOF once our copies of the slaves are gone too
+        self.cmd.stdout(Stdio::null());
+        self.cmd.stderr(Stdio::null());
+
+        let child = child?;
+        // The command leads its own session, so its process group id is its pid
+        let watch = supervisor.watch(-(child.id() as libc::pid_t));
+
+        Ok(OutputStream::new(
+            child,
+            watch,
+            stdout_pty.master,
+            stderr_pty.master,
+            Some(resize),
+        ))
+    }
+
+    fn stream_from_pipes(&mut self) -> Result<OutputStream, Error> {
         self.cmd.stdout(Stdio::piped());
         self.cmd.stderr(Stdio::piped());
+        // A process group of its own lets signals reach everything it starts
+        self.cmd.process_group(0);
 
-        let mut child = self.cmd.spawn().expect("failed to spawn command");
+        let supervisor = Supervisor::new(true, self.timeout)?;
+        let mut child = self.spawn()?;
+        let watch = supervisor.watch(-(child.id() as libc::pid_t));
         let stdout_pipe = child.stdout.take().unwrap();
-        let stdout_thread = tee(BufReader::new(stdout_pipe), stdout());
         let stderr_pipe = child.stderr.take().unwrap();
-        let stderr_thread = tee(BufReader::new(stderr_pipe), stderr());
-        let stdout_output = stdout_thread.join().expect("failed to join stdout thread");
-        let stderr_output = stderr_thread.join().expect("failed to join stderr thread");
-        let exit_status = child.wait()?;
-
-        Ok(Output {
-            stdout: stdout_output?.into(),
-            stderr: stderr_output?.into(),
-            status: exit_status,
-        })
+
+        Ok(OutputStream::new(
+            child,
+            watch,
+            stdout_pipe,
+            stderr_pipe,
+            None,
+        ))
     }
 }
@@ -0,0 +1,190 @@
+use std::env;
+
+use crate::audit::AuditLog;
+use crate::cmd_runner::{CmdType, OutputMode, INTERACTIVE_PROGRAMS};
+use crate::context::Context;
+use crate::keymap::{Keymap, Order};
+use crate::output::DEFAULT_MEMORY_CAP;
+use crate::quickfix::Location;
+use crate::shell::Shell;
+use crate::template;
+use crate::utils::split_words;
+
+// Opens a quickfix location when neither `$VISUAL` nor `$EDITOR` is set,
+// see `Config::editor_cmd`
+pub const DEFAULT_EDITOR: &str = "hx {file}:{line}";
+
+// Editors that take the line as `+N` before the file
+const PLUS_LINE_EDITORS: &[&str] = &["vi", "vim", "nvim", "nano", "emacs", "emacsclient", "kak"];
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub interactive_programs: Vec<String>,
+    pub shell: Shell,
+    // Bytes of a command's output kept in memory, the rest is spilled to a file
+    pub output_memory_cap: usize,
+    // Where executed commands are recorded, if anywhere
+    pub audit_log: Option<AuditLog>,
+    // Captured output is shown in a pager before going back to the palette
+    pub pager: bool,
+    // Locations in a failed command's output are offered in a picker
+    pub quickfix: bool,
+    // Every command is shown in the line editor before it's run
+    pub edit: bool,
+    // Commands are described instead of run, see `CmdRunner::describe`
+    pub dry_run: bool,
+    pub editor: String,
+    // How the palette sorts keymaps
+    pub order: Order,
+    pub output_mode: OutputMode,
+    // Whether the exit status is printed after each command
+    pub show_status: bool,
+    // What the built-in placeholders need to know about the session
+    pub context: Context,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            interactive_programs: INTERACTIVE_PROGRAMS
+                .iter()
+                .map(|program| program.to_string())
+                .collect(),
+            shell: Shell::default(),
+            output_memory_cap: DEFAULT_MEMORY_CAP,
+            audit_log: None,
+            pager: false,
+            quickfix: false,
+            edit: false,
+            dry_run: false,
+            editor: default_editor(),
+            order: Order::default(),
+            output_mode: OutputMode::default(),
+            show_status: true,
+            context: Context::default(),
+        }
+    }
+}
+
+impl Config {
+    pub fn with_interactive_programs<S: AsRef<str>>(mut self, programs: &[S]) -> Self {
+        self.interactive_programs = programs
+            .iter()
+            .map(|program| program.as_ref().to_owned())
+            .collect();
+        self
+    }
+
+    pub fn with_interactive_program<S: AsRef<str>>(mut self, program: S) -> Self {
+        self.interactive_programs.push(program.as_ref().to_owned());
+        self
+    }
+
+    pub fn with_shell(mut self, shell: Shell) -> Self {
+        self.shell = shell;
+        self
+    }
+
+    pub fn with_output_memory_cap(mut self, output_memory_cap: usize) -> Self {
+        self.output_memory_cap = output_memory_cap;
+        self
+    }
+
+    pub fn with_audit_log(mut self, audit_log: AuditLog) -> Self {
+        self.audit_log = Some(audit_log);
+        self
+    }
+
+    pub fn with_pager(mut self, pager: bool) -> Self {
+        self.pager = pager;
+        self
+    }
+
+    pub fn with_quickfix(mut self, quickfix: bool) -> Self {
+        self.quickfix = quickfix;
+        self
+    }
+
+    pub fn with_edit(mut self, edit: bool) -> Self {
+        self.edit = edit;
+        self
+    }
+
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    pub fn with_order(mut self, order: Order) -> Self {
+        self.order = order;
+        self
+    }
+
+    pub fn with_context(mut self, context: Context) -> Self {
+        self.context = context;
+        self
+    }
+
+    pub fn with_output_mode(mut self, output_mode: OutputMode) -> Self {
+        self.output_mode = output_mode;
+        self
+    }
+
+    pub fn with_show_status(mut self, show_status: bool) -> Self {
+        self.show_status = show_status;
+        self
+    }
+
+    // A command with `{file}`, `{line}` and `{column}` placeholders
+    pub fn with_editor<S: AsRef<str>>(mut self, editor: S) -> Self {
+        self.editor = editor.as_ref().to_owned();
+        self
+    }
+
+    pub fn editor_cmd(&self, location: &Location) -> String {
+        template::render(&self.editor, |name| match name {
+            "file" => Some(location.path.clone()),
+            "line" => Some(location.line.to_string()),
+            "column" => Some(location.column.to_string()),
+            _ => None,
+        })
+    }
+
+    // The keymap's own flag wins over the list of interactive programs
+    pub fn cmd_type(&self, keymap: &Keymap, cmd_str: &str) -> CmdType {
+        match keymap.interactive {
+            Some(interactive) => CmdType::from(interactive),
+            None => CmdType::detect(cmd_str, &self.interactive_programs),
+        }
+    }
+
+    pub fn shell<'a>(&'a self, keymap: &'a Keymap) -> &'a Shell {
+        keymap.shell.as_ref().unwrap_or(&self.shell)
+    }
+}
+
+// `$VISUAL` or `$EDITOR` as an editor command, or `DEFAULT_EDITOR`
+fn default_editor() -> String {
+    ["VISUAL", "EDITOR"]
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|editor| !editor.trim().is_empty())
+        .map_or_else(
+            || DEFAULT_EDITOR.to_owned(),
+            |editor| editor_template(&editor),
+        )
+}
+
+// The placeholders `editor` (e.g. `vim` or `code --wait`) takes a location
+// with. Editors it doesn't know only get the file.
+pub fn editor_template(editor: &str) -> String {
+    let program = split_words(editor).into_iter().next().unwrap_or_default();
+    let program = program.rsplit('/').next().unwrap_or_default();
+
+    match program {
+        "hx" | "helix" | "subl" | "zed" => format!("{} {{file}}:{{line}}:{{column}}", editor),
+        "code" | "codium" => format!("{} --goto {{file}}:{{line}}:{{column}}", editor),
+        _ if PLUS_LINE_EDITORS.contains(&program) => format!("{} +{{line}} {{file}}", editor),
+        _ => format!("{} {{file}}", editor),
+    }
+}
@@ -0,0 +1,595 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use toml_edit::{value as toml_value, Array, ArrayOfTables, DocumentMut, Item, Table, Value};
+
+use crate::config::Config;
+use crate::error::Error;
+use crate::keymap::{self, Keymap, Order};
+use crate::shell::Shell;
+
+// The project's config, looked for in the working directory and its parents
+pub const PROJECT_FILE_NAME: &str = ".quicommand.toml";
+
+const SETTINGS: &[&str] = &[
+    "shell",
+    "editor",
+    "pager",
+    "quickfix",
+    "order",
+    "output_memory_cap",
+    "interactive_programs",
+    "keymap",
+];
+const KEYMAP_FIELDS: &[&str] = &[
+    "key",
+    "name",
+    "description",
+    "cmd",
+    "prompt",
+    "prompt_name",
+    "pass_input",
+    "secret",
+    "interactive",
+    "shell",
+    "timeout",
+    "output",
+    "when",
+];
+
+// Settings and keymaps read from one TOML file, e.g.
+//
+//     shell = "bash -c"
+//
+//     [[keymap]]
+//     key = "c"
+//     name = "commit"
+//     cmd = "git commit -m {message}"
+//     prompt = "Commit message:"
+//     prompt_name = "message"
+//     when = ["exists:.git"]
+#[derive(Debug, Clone, Default)]
+pub struct ConfigFile {
+    pub path: PathBuf,
+    pub settings: Settings,
+    pub keymaps: Vec<Keymap>,
+}
+
+// What a file sets, with everything else left to other files or defaults
+#[derive(Debug, Clone, Default)]
+pub struct Settings {
+    pub shell: Option<Shell>,
+    pub editor: Option<String>,
+    pub pager: Option<bool>,
+    pub quickfix: Option<bool>,
+    pub order: Option<Order>,
+    // In bytes
+    pub output_memory_cap: Option<usize>,
+    pub interactive_programs: Vec<String>,
+}
+
+impl ConfigFile {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+
+        Self::parse(path, &text)
+    }
+
+    // `path` is only used for error messages and as the keymaps' source
+    pub fn parse<P: AsRef<Path>>(path: P, text: &str) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let in_file = |message: String| Error::Config(format!("{}: {}", path.display(), message));
+        let document: DocumentMut = text
+            .parse()
+            .map_err(|e: toml_edit::TomlError| in_file(e.to_string().trim_end().to_owned()))?;
+        let table = document.as_table();
+
+        check_fields(table, SETTINGS).map_err(in_file)?;
+
+        let settings = Settings {
+            shell: string(table, "shell")
+                .map_err(in_file)?
+                .map(|shell| shell.parse().map_err(|e| in_file(config_message(e))))
+                .transpose()?,
+            editor: string(table, "editor").map_err(in_file)?,
+            pager: bool(table, "pager").map_err(in_file)?,
+            quickfix: bool(table, "quickfix").map_err(in_file)?,
+            order: string(table, "order")
+                .map_err(in_file)?
+                .map(|order| order.parse().map_err(|e| in_file(config_message(e))))
+                .transpose()?,
+            output_memory_cap: size(table, "output_memory_cap").map_err(in_file)?,
+            interactive_programs: strings(table, "interactive_programs").map_err(in_file)?,
+        };
+        let keymaps = match table.get("keymap") {
+            None => Vec::new(),
+            Some(item) => item
+                .as_array_of_tables()
+                .ok_or_else(|| in_file("`keymap` has to be an array of tables".to_owned()))?
+                .iter()
+                .enumerate()
+                .map(|(i, table)| {
+                    keymap(table)
+                        .map(|keymap| keymap.with_source(path))
+                        .map_err(|message| in_file(format!("keymap {}: {}", i + 1, message)))
+                })
+                .collect::<Result<_, _>>()?,
+        };
+
+        Ok(Self {
+            path: path.to_owned(),
+            settings,
+            keymaps,
+        })
+    }
+
+    pub fn apply(&self, mut config: Config) -> Config {
+        if let Some(shell) = &self.settings.shell {
+            config = config.with_shell(shell.clone());
+        }
+
+        if let Some(editor) = &self.settings.editor {
+            config = config.with_editor(editor);
+        }
+
+        if let Some(pager) = self.settings.pager {
+            config = config.with_pager(pager);
+        }
+
+        if let Some(quickfix) = self.settings.quickfix {
+            config = config.with_quickfix(quickfix);
+        }
+
+        if let Some(order) = self.settings.order {
+            config = config.with_order(order);
+        }
+
+        if let Some(output_memory_cap) = self.settings.output_memory_cap {
+            config = config.with_output_memory_cap(output_memory_cap);
+        }
+
+        for program in &self.settings.interactive_programs {
+            config = config.with_interactive_program(program);
+        }
+
+        config
+    }
+}
+
+// `$XDG_CONFIG_HOME/quicommand/config.toml`, which is usually
+// `~/.config/quicommand/config.toml`
+pub fn user_path() -> Option<PathBuf> {
+    let config_home = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+
+    Some(config_home.join("quicommand").join("config.toml"))
+}
+
+// The nearest `.quicommand.toml` in `dir` or one of its parents
+pub fn project_path(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join(PROJECT_FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+// The user's config and then the project's, for the ones that exist
+pub fn load_all() -> Result<Vec<ConfigFile>, Error> {
+    let project_path = project_path(&env::current_dir()?);
+
+    user_path()
+        .filter(|path| path.is_file())
+        .into_iter()
+        .chain(project_path)
+        .map(ConfigFile::load)
+        .collect()
+}
+
+// A keymap in a later file replaces the one with the same key in an earlier
+// file, keeping its place
+pub fn merge_keymaps(files: &[ConfigFile]) -> Vec<Keymap> {
+    let mut keymaps: Vec<Keymap> = Vec::new();
+
+    for keymap in files.iter().flat_map(|file| &file.keymaps) {
+        match keymaps.iter_mut().find(|k| k.key == keymap.key) {
+            Some(existing) => *existing = keymap.clone(),
+            None => keymaps.push(keymap.clone()),
+        }
+    }
+
+    keymaps
+}
+
+// Adds `keymap` to the end of the file at `path`, which is created if it
+// doesn't exist. The rest of the file is left as it was, comments included.
+pub fn append_keymap(path: &Path, keymap: &Keymap) -> Result<(), Error> {
+    edit_keymaps(path, |keymaps| {
+        keymaps.push(keymap_table(keymap));
+
+        Ok(())
+    })
+}
+
+// Adds `keymap` to the file at `path` like `append_keymap`, and to
+// `keymaps`, the ones the palette shows. When those are the built-in
+// keymaps, they're written to the file first: they're only used while no
+// file has keymaps, so they'd be gone the next time otherwise.
+pub fn add_keymap(path: &Path, keymaps: &mut Vec<Keymap>, keymap: Keymap) -> Result<(), Error> {
+    let built_in = !keymaps.is_empty() && keymaps.iter().all(|k| k.source.is_none());
+
+    edit_keymaps(path, |tables| {
+        if built_in {
+            for keymap in keymaps.iter() {
+                tables.push(keymap_table(keymap));
+            }
+        }
+
+        tables.push(keymap_table(&keymap));
+
+        Ok(())
+    })?;
+
+    if built_in {
+        for keymap in keymaps.iter_mut() {
+            keymap.source = Some(path.to_owned());
+        }
+    }
+
+    keymaps.push(keymap.with_source(path));
+
+    Ok(())
+}
+
+// Changes the keymap bound to `key` to `keymap`. Only the fields that differ
+// are touched, and those keep their comments.
+pub fn update_keymap(path: &Path, key: char, keymap: &Keymap) -> Result<(), Error> {
+    edit_keymaps(path, |keymaps| {
+        let table = keymaps.get_mut(index(keymaps, key)?).unwrap();
+        let old = keymap_table(&self::keymap(table)?);
+        let new = keymap_table(keymap);
+
+        for &field in KEYMAP_FIELDS {
+            let rendered = |table: &Table| table.get(field).map(Item::to_string);
+
+            if rendered(&old) == rendered(&new) {
+                continue;
+            }
+
+            match new.get(field).and_then(Item::as_value) {
+                Some(value) => {
+                    let mut value = value.clone();
+
+                    if let Some(old) = value_mut(table, field) {
+                        *value.decor_mut() = old.decor().clone();
+                    }
+
+                    table.insert(field, Item::Value(value));
+                }
+                None => {
+                    table.remove(field);
+                }
+            }
+        }
+
+        Ok(())
+    })
+}
+
+pub fn remove_keymap(path: &Path, key: char) -> Result<(), Error> {
+    edit_keymaps(path, |keymaps| {
+        keymaps.remove(index(keymaps, key)?);
+
+        Ok(())
+    })
+}
+
+// Swaps the places of the keymaps bound to `a` and `b`. Comments above a
+// keymap go with it, while blank lines stay where they were.
+pub fn swap_keymaps(path: &Path, a: char, b: char) -> Result<(), Error> {
+    edit_keymaps(path, |keymaps| {
+        let (a, b) = (index(keymaps, a)?, index(keymaps, b)?);
+        let table_a = keymaps.get(a).unwrap().clone();
+        let table_b = keymaps.get(b).unwrap().clone();
+
+        keymaps.replace(a, moved_table(table_b.clone(), &table_a));
+        keymaps.replace(b, moved_table(table_a, &table_b));
+
+        Ok(())
+    })
+}
+
+// `table`, to be put where `old` was
+fn moved_table(mut table: Table, old: &Table) -> Table {
+    let prefix = |table: &Table| {
+        let prefix = table
+            .decor()
+            .prefix()
+            .and_then(|p| p.as_str())
+            .unwrap_or("");
+        // The blank lines up to the first comment
+        let blank_len = prefix.len() - prefix.trim_start().len();
+        let blank_len = prefix[..blank_len].rfind('\n').map_or(0, |i| i + 1);
+
+        (
+            prefix[..blank_len].to_owned(),
+            prefix[blank_len..].to_owned(),
+        )
+    };
+    let (blank, _) = prefix(old);
+    let (_, comments) = prefix(&table);
+
+    // Where a table is written is part of it, so that stays put too
+    table.set_position(old.position());
+    table.decor_mut().set_prefix(blank + &comments);
+    table
+}
+
+// Reads the file at `path` (or nothing, if it doesn't exist yet), lets
+// `edit` change its keymaps and writes it back, as long as it still loads
+fn edit_keymaps<F>(path: &Path, edit: F) -> Result<(), Error>
+where
+    F: FnOnce(&mut ArrayOfTables) -> Result<(), String>,
+{
+    let in_file = |message: String| Error::Config(format!("{}: {}", path.display(), message));
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.into()),
+    };
+    // A broken file is left for the user to fix
+    ConfigFile::parse(path, &text)?;
+
+    let mut document: DocumentMut = text
+        .parse()
+        .map_err(|e: toml_edit::TomlError| in_file(e.to_string().trim_end().to_owned()))?;
+    let keymaps = document
+        .entry("keymap")
+        .or_insert(Item::ArrayOfTables(ArrayOfTables::new()))
+        .as_array_of_tables_mut()
+        .ok_or_else(|| in_file("`keymap` has to be an array of tables".to_owned()))?;
+
+    edit(keymaps).map_err(in_file)?;
+
+    let text = document.to_string();
+
+    // What's written has to load again
+    ConfigFile::parse(path, &text)?;
+    fs::write(path, text)?;
+
+    Ok(())
+}
+
+// The last keymap bound to `key`, which is the one that counts
+fn index(keymaps: &ArrayOfTables, key: char) -> Result<usize, String> {
+    let key = key.to_string();
+
+    (0..keymaps.len())
+        .rev()
+        .find(|&i| {
+            string(keymaps.get(i).unwrap(), "key")
+                .ok()
+                .flatten()
+                .as_ref()
+                == Some(&key)
+        })
+        .ok_or_else(|| format!("there's no keymap `{}`", key))
+}
+
+// The opposite of `keymap`, leaving out what's the same as the default
+pub fn keymap_table(keymap: &Keymap) -> Table {
+    let mut table = Table::new();
+    let defaults = Keymap::new(keymap.key, &keymap.cmd);
+
+    table.insert("key", toml_value(keymap.key.to_string()));
+
+    if let Some(name) = &keymap.name {
+        table.insert("name", toml_value(name));
+    }
+
+    if keymap.description != defaults.description {
+        table.insert("description", toml_value(&keymap.description));
+    }
+
+    table.insert("cmd", toml_value(&keymap.cmd));
+
+    if let Some(prompt) = &keymap.prompt {
+        table.insert("prompt", toml_value(prompt));
+    }
+
+    if let Some(prompt_name) = &keymap.prompt_name {
+        table.insert("prompt_name", toml_value(prompt_name));
+    }
+
+    if keymap.pass_input != defaults.pass_input {
+        table.insert("pass_input", toml_value(keymap.pass_input.to_string()));
+    }
+
+    if keymap.secret {
+        table.insert("secret", toml_value(true));
+    }
+
+    if let Some(interactive) = keymap.interactive {
+        table.insert("interactive", toml_value(interactive));
+    }
+
+    if let Some(shell) = &keymap.shell {
+        table.insert("shell", toml_value(shell.to_string()));
+    }
+
+    if let Some(timeout) = keymap.timeout {
+        let timeout = match timeout.subsec_nanos() {
+            0 => toml_value(timeout.as_secs() as i64),
+            _ => toml_value(timeout.as_secs_f64()),
+        };
+
+        table.insert("timeout", timeout);
+    }
+
+    if let Some(output) = keymap.output {
+        table.insert("output", toml_value(output.to_string()));
+    }
+
+    if !keymap.conditions.is_empty() {
+        let conditions: Array = keymap
+            .conditions
+            .iter()
+            .map(|condition| condition.to_string())
+            .collect();
+
+        table.insert("when", toml_value(conditions));
+    }
+
+    table
+}
+
+fn keymap(table: &Table) -> Result<Keymap, String> {
+    check_fields(table, KEYMAP_FIELDS)?;
+
+    let key = string(table, "key")?.ok_or("`key` is missing")?;
+    let mut chars = key.chars();
+    let key = match (chars.next(), chars.next()) {
+        (Some(key), None) => key,
+        _ => return Err(format!("`{}` isn't a single key", key)),
+    };
+
+    if keymap::is_reserved(key) {
+        return Err(format!("`{}` is used by the palette", key));
+    }
+
+    let cmd = string(table, "cmd")?.ok_or("`cmd` is missing")?;
+    let mut keymap = Keymap::new(key, cmd);
+
+    if let Some(name) = string(table, "name")? {
+        keymap = keymap.with_name(name);
+    }
+
+    if let Some(description) = string(table, "description")? {
+        keymap = keymap.with_description(description);
+    }
+
+    if let Some(prompt) = string(table, "prompt")? {
+        keymap = keymap.with_prompt(prompt);
+    }
+
+    if let Some(prompt_name) = string(table, "prompt_name")? {
+        keymap = keymap.with_prompt_name(prompt_name);
+    }
+
+    if let Some(pass_input) = string(table, "pass_input")? {
+        keymap = keymap.with_pass_input(pass_input.parse().map_err(config_message)?);
+    }
+
+    if let Some(secret) = bool(table, "secret")? {
+        keymap = keymap.with_secret(secret);
+    }
+
+    if let Some(interactive) = bool(table, "interactive")? {
+        keymap = keymap.with_interactive(interactive);
+    }
+
+    if let Some(shell) = string(table, "shell")? {
+        keymap = keymap.with_shell(shell.parse().map_err(config_message)?);
+    }
+
+    if let Some(timeout) = seconds(table, "timeout")? {
+        keymap = keymap.with_timeout(timeout);
+    }
+
+    if let Some(output) = string(table, "output")? {
+        keymap = keymap.with_output(output.parse().map_err(config_message)?);
+    }
+
+    for condition in strings(table, "when")? {
+        keymap = keymap.with_condition(condition.parse().map_err(config_message)?);
+    }
+
+    Ok(keymap)
+}
+
+// Values are parsed like everywhere else, so their errors only need the
+// file added
+fn config_message(error: Error) -> String {
+    match error {
+        Error::Config(message) => message,
+        e => e.to_string(),
+    }
+}
+
+fn check_fields(table: &Table, fields: &[&str]) -> Result<(), String> {
+    match table.iter().find(|(name, _)| !fields.contains(name)) {
+        Some((name, _)) => Err(format!("unknown field `{}`", name)),
+        None => Ok(()),
+    }
+}
+
+fn value<'a>(table: &'a Table, name: &str) -> Option<&'a Value> {
+    table.get(name).and_then(Item::as_value)
+}
+
+fn value_mut<'a>(table: &'a mut Table, name: &str) -> Option<&'a mut Value> {
+    table.get_mut(name).and_then(Item::as_value_mut)
+}
+
+fn string(table: &Table, name: &str) -> Result<Option<String>, String> {
+    match table.get(name) {
+        None => Ok(None),
+        Some(item) => item
+            .as_str()
+            .map(|s| Some(s.to_owned()))
+            .ok_or_else(|| format!("`{}` has to be a string", name)),
+    }
+}
+
+fn bool(table: &Table, name: &str) -> Result<Option<bool>, String> {
+    match table.get(name) {
+        None => Ok(None),
+        Some(item) => item
+            .as_bool()
+            .map(Some)
+            .ok_or_else(|| format!("`{}` has to be true or false", name)),
+    }
+}
+
+fn strings(table: &Table, name: &str) -> Result<Vec<String>, String> {
+    if !table.contains_key(name) {
+        return Ok(Vec::new());
+    }
+
+    let not_strings = || format!("`{}` has to be a list of strings", name);
+
+    value(table, name)
+        .and_then(Value::as_array)
+        .ok_or_else(not_strings)?
+        .iter()
+        .map(|value| value.as_str().map(str::to_owned).ok_or_else(not_strings))
+        .collect()
+}
+
+fn size(table: &Table, name: &str) -> Result<Option<usize>, String> {
+    match table.get(name) {
+        None => Ok(None),
+        Some(item) => item
+            .as_integer()
+            .and_then(|size| usize::try_from(size).ok())
+            .map(Some)
+            .ok_or_else(|| format!("`{}` has to be a number of bytes", name)),
+    }
+}
+
+fn seconds(table: &Table, name: &str) -> Result<Option<Duration>, String> {
+    let Some(item) = table.get(name) else {
+        return Ok(None);
+    };
+    let secs = match (item.as_integer(), item.as_float()) {
+        (Some(secs), _) if secs >= 0 => secs as f64,
+        (_, Some(secs)) if secs >= 0.0 && secs.is_finite() => secs,
+        _ => return Err(format!("`{}` has to be a number of seconds", name)),
+    };
+
+    Ok(Some(Duration::from_secs_f64(secs)))
+}
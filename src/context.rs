@@ -0,0 +1,85 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use chrono::Local;
+
+use crate::config_file::PROJECT_FILE_NAME;
+use crate::output::{Output, Stream};
+use crate::pager;
+
+// What the built-in placeholders (listed in the README) are filled with,
+// besides what's looked up when a command is rendered.
+//
+// `{git.branch}` and `{git.remote}` are left as they are outside a
+// repository (or on a detached HEAD), so the command fails visibly instead
+// of running with an empty value.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Context {
+    pub last_output: Option<String>,
+}
+
+impl Context {
+    // The previous command's stdout, cleaned up like the pager does
+    pub fn with_last_output(mut self, output: &Output) -> Self {
+        let lines: Vec<String> = pager::lines(output)
+            .into_iter()
+            .filter(|line| line.stream == Stream::Stdout)
+            .map(|line| line.text)
+            .collect();
+
+        self.last_output = Some(lines.join("\n"));
+        self
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<String> {
+        if let Some(var) = name.strip_prefix("env.") {
+            return Some(env::var(var).unwrap_or_default());
+        }
+
+        match name {
+            "cwd" => Some(cwd().display().to_string()),
+            "project_root" => Some(project_root(&cwd()).display().to_string()),
+            "git.branch" => git(&["branch", "--show-current"]),
+            "git.remote" => git_remote(),
+            "date" => Some(Local::now().format("%Y-%m-%d").to_string()),
+            "time" => Some(Local::now().format("%H:%M:%S").to_string()),
+            "user" => env::var("USER").or_else(|_| env::var("LOGNAME")).ok(),
+            "last_output" => Some(self.last_output.clone().unwrap_or_default()),
+            _ => None,
+        }
+    }
+}
+
+pub fn project_root(dir: &Path) -> PathBuf {
+    dir.ancestors()
+        .find(|dir| dir.join(PROJECT_FILE_NAME).is_file() || dir.join(".git").exists())
+        .unwrap_or(dir)
+        .to_owned()
+}
+
+fn cwd() -> PathBuf {
+    env::current_dir().unwrap_or_default()
+}
+
+fn git_remote() -> Option<String> {
+    let branch = git(&["branch", "--show-current"]);
+    let upstream =
+        branch.and_then(|branch| git(&["config", "--get", &format!("branch.{}.remote", branch)]));
+
+    upstream.or_else(|| git(&["remote"])?.lines().next().map(str::to_owned))
+}
+
+// The trimmed output of a git command that worked and printed something
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    let text = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+
+    (!text.is_empty()).then_some(text)
+}
@@ -0,0 +1,68 @@
+use std::convert::From;
+use std::fmt;
+use std::io;
+
+use crate::input::InputError;
+
+#[derive(Debug)]
+pub enum Error {
+    Spawn { cmd: String, source: io::Error },
+    Io(io::Error),
+    Config(String),
+    Input(InputError),
+    // Bad command line arguments
+    Usage(String),
+}
+
+impl Error {
+    // Exit codes follow sysexits.h, except for commands that can't be
+    // spawned, which get the shell's 127
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Spawn { .. } => 127,
+            Error::Io(_) => 74,
+            Error::Config(_) => 78,
+            Error::Input(_) => 65,
+            Error::Usage(_) => 64,
+        }
+    }
+
+    // A thread that panicked took its error with it
+    pub(crate) fn thread_panicked(name: &str) -> Self {
+        Error::Io(io::Error::other(format!("{} thread panicked", name)))
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<InputError> for Error {
+    fn from(error: InputError) -> Self {
+        Error::Input(error)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Spawn { cmd, source } => write!(f, "Failed to run `{}`: {}", cmd, source),
+            Error::Io(e) => write!(f, "I/O Error: {}", e),
+            Error::Config(message) => write!(f, "Invalid config: {}", message),
+            Error::Input(e) => write!(f, "{}", e),
+            Error::Usage(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Spawn { source, .. } => Some(source),
+            Error::Io(e) => Some(e),
+            Error::Config(_) | Error::Input(_) | Error::Usage(_) => None,
+        }
+    }
+}
@@ -50,21 +50,72 @@ pub fn input_from_keys<T: TermCursor + Write>(
     input_keys: impl Iterator<Item = Result<Key, io::Error>>,
     stdout: &mut T,
 ) -> Result<Input, InputError> {
-    let input = String::new();
+    edit_from_keys(String::new(), input_keys, stdout)
+}
+
+// Like `input_from_keys`, but starting from `input` instead of nothing
+pub fn edit_from_keys<T: TermCursor + Write>(
+    input: String,
+    input_keys: impl Iterator<Item = Result<Key, io::Error>>,
+    stdout: &mut T,
+) -> Result<Input, InputError> {
+    read_line(input, &[], input_keys, stdout)
+}
+
+// Like `input_from_keys`, but Up and Down go through `history` (oldest
+// first) the way a shell's do
+pub fn input_with_history<T: TermCursor + Write>(
+    history: &[String],
+    input_keys: impl Iterator<Item = Result<Key, io::Error>>,
+    stdout: &mut T,
+) -> Result<Input, InputError> {
+    read_line(String::new(), history, input_keys, stdout)
+}
+
+fn read_line<T: TermCursor + Write>(
+    input: String,
+    history: &[String],
+    input_keys: impl Iterator<Item = Result<Key, io::Error>>,
+    stdout: &mut T,
+) -> Result<Input, InputError> {
     let mut term_writer = TermWriter::new(input, stdout);
+    // `history.len()` is the line being typed, which is kept in `typed`
+    // while going through the history
+    let mut position = history.len();
+    let mut typed = String::new();
+
+    if !term_writer.input.is_empty() {
+        term_writer.show()?;
+        term_writer.stdout.flush()?;
+    }
 
     for key in input_keys {
-        match key.unwrap() {
+        match key? {
             Key::Char('\n') => return term_writer.enter(),
             Key::Esc => return Ok(Input::Cancel),
             Key::Char(c) => term_writer.char(c)?,
             Key::Left => term_writer.left()?,
             Key::Right => term_writer.right()?,
             Key::Backspace => term_writer.backspace()?,
+            Key::Up if position > 0 => {
+                if position == history.len() {
+                    typed = term_writer.input.clone();
+                }
+
+                position -= 1;
+                term_writer.replace(history[position].clone())?;
+            }
+            Key::Down if position < history.len() => {
+                position += 1;
+
+                let input = history.get(position).unwrap_or(&typed).clone();
+
+                term_writer.replace(input)?;
+            }
             _ => {}
         }
 
-        term_writer.stdout.flush().unwrap();
+        term_writer.stdout.flush()?;
     }
 
     let input = term_writer.input.trim().to_owned();
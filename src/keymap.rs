@@ -1,9 +1,167 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::audit::REDACTED;
+use crate::context::Context;
+use crate::error::Error;
+use crate::output::Retention;
+use crate::shell::{Shell, Syntax};
+use crate::template;
+use crate::utils::{fish_quote, shell_quote};
+
+// The prompt's name when a keymap doesn't set one (`{}` works either way)
+pub const DEFAULT_PROMPT_NAME: &str = "input";
+
+// What commands typed on the palette's command line are recorded under
+pub const AD_HOC_KEY: char = ':';
+
+// Keys the palette uses itself, which keymaps can't be bound to
+pub fn is_reserved(key: char) -> bool {
+    matches!(key, 'q' | AD_HOC_KEY)
+}
+
+// Where the prompt's input goes besides the command's placeholders
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum PassInput {
+    #[default]
+    Cmd,
+    // An environment variable named after the prompt (see `prompt_env_var`)
+    Env,
+    // The command's stdin, followed by a newline
+    Stdin,
+}
+
+impl FromStr for PassInput {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cmd" => Ok(PassInput::Cmd),
+            "env" => Ok(PassInput::Env),
+            "stdin" => Ok(PassInput::Stdin),
+            _ => Err(Error::Config(format!(
+                "`{}` isn't one of `cmd`, `env` or `stdin`",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for PassInput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PassInput::Cmd => write!(f, "cmd"),
+            PassInput::Env => write!(f, "env"),
+            PassInput::Stdin => write!(f, "stdin"),
+        }
+    }
+}
+
+// The order keymaps are shown in the palette, which doesn't change their keys
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Order {
+    // As they're listed in the config files
+    #[default]
+    Defined,
+    // By description
+    Alphabetical,
+    // Most often and most recently run first, see `audit::Frecency`
+    Frecency,
+}
+
+impl FromStr for Order {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "defined" => Ok(Order::Defined),
+            "alphabetical" => Ok(Order::Alphabetical),
+            "frecency" => Ok(Order::Frecency),
+            _ => Err(Error::Config(format!(
+                "`{}` isn't one of `defined`, `alphabetical` or `frecency`",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Order {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Order::Defined => write!(f, "defined"),
+            Order::Alphabetical => write!(f, "alphabetical"),
+            Order::Frecency => write!(f, "frecency"),
+        }
+    }
+}
+
+// When a keymap is offered at all, e.g. `exists:Cargo.toml` or `env:CI`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    // A file or directory, relative to the working directory
+    Exists(PathBuf),
+    // A non-empty environment variable
+    Env(String),
+}
+
+impl Condition {
+    pub fn is_met(&self) -> bool {
+        match self {
+            Condition::Exists(path) => Path::new(path).exists(),
+            Condition::Env(name) => env::var_os(name).is_some_and(|value| !value.is_empty()),
+        }
+    }
+}
+
+impl FromStr for Condition {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("exists", path)) if !path.is_empty() => Ok(Condition::Exists(path.into())),
+            Some(("env", name)) if !name.is_empty() => Ok(Condition::Env(name.to_owned())),
+            _ => Err(Error::Config(format!(
+                "`{}` isn't a condition like `exists:PATH` or `env:NAME`",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Condition::Exists(path) => write!(f, "exists:{}", path.display()),
+            Condition::Env(name) => write!(f, "env:{}", name),
+        }
+    }
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct Keymap {
     pub key: char,
+    // An alias for running it from the command line (`quicommand run commit`)
+    pub name: Option<String>,
     pub cmd: String,
     pub description: String,
     pub prompt: Option<String>,
+    pub prompt_name: Option<String>,
+    pub pass_input: PassInput,
+    pub interactive: Option<bool>,
+    pub shell: Option<Shell>,
+    pub timeout: Option<Duration>,
+    pub output: Option<Retention>,
+    // The prompt's input is left out of the audit log
+    pub secret: bool,
+    // All of them have to be met for the keymap to be offered
+    pub conditions: Vec<Condition>,
+    // The config file it was read from, if any
+    pub source: Option<PathBuf>,
 }
 
 impl Keymap {
@@ -19,13 +177,230 @@ impl Keymap {
         }
     }
 
+    pub fn with_name<S: AsRef<str>>(mut self, name: S) -> Self {
+        self.name = Some(name.as_ref().to_owned());
+        self
+    }
+
     pub fn with_prompt<S: AsRef<str>>(mut self, prompt: S) -> Self {
         self.prompt = Some(prompt.as_ref().to_owned());
         self
     }
 
+    pub fn with_prompt_name<S: AsRef<str>>(mut self, name: S) -> Self {
+        self.prompt_name = Some(name.as_ref().to_owned());
+        self
+    }
+
+    pub fn with_pass_input(mut self, pass_input: PassInput) -> Self {
+        self.pass_input = pass_input;
+        self
+    }
+
     pub fn with_description<S: AsRef<str>>(mut self, description: S) -> Self {
         self.description = description.as_ref().to_owned();
         self
     }
+
+    pub fn with_interactive(mut self, interactive: bool) -> Self {
+        self.interactive = Some(interactive);
+        self
+    }
+
+    pub fn with_shell(mut self, shell: Shell) -> Self {
+        self.shell = Some(shell);
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    // E.g. `Retention::Tail(200)` for commands whose output is only
+    // interesting at the end
+    pub fn with_output(mut self, output: Retention) -> Self {
+        self.output = Some(output);
+        self
+    }
+
+    pub fn with_secret(mut self, secret: bool) -> Self {
+        self.secret = secret;
+        self
+    }
+
+    pub fn with_condition(mut self, condition: Condition) -> Self {
+        self.conditions.push(condition);
+        self
+    }
+
+    pub fn with_source<P: AsRef<Path>>(mut self, source: P) -> Self {
+        self.source = Some(source.as_ref().to_owned());
+        self
+    }
+
+    // A command typed on the palette's command line, which only exists to
+    // run it like a keymap's
+    pub fn ad_hoc<S: AsRef<str>>(cmd: S) -> Self {
+        Self::new(AD_HOC_KEY, cmd).with_description("Ad-hoc command")
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.conditions.iter().all(Condition::is_met)
+    }
+
+    pub fn prompt_name(&self) -> &str {
+        self.prompt_name.as_deref().unwrap_or(DEFAULT_PROMPT_NAME)
+    }
+
+    // E.g. `QC_MESSAGE` for a prompt named `message`
+    pub fn prompt_env_var(&self) -> String {
+        let name: String = self
+            .prompt_name()
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' => c.to_ascii_uppercase(),
+                _ => '_',
+            })
+            .collect();
+
+        format!("QC_{}", name)
+    }
+
+    // The command as a user would type it in their own shell (written in
+    // `syntax`), with the input passed the way `pass_input` says
+    pub fn shell_cmd(&self, input: Option<&str>, context: &Context, syntax: Syntax) -> String {
+        let cmd = self.render_cmd(input, context);
+        let Some(input) = input else {
+            return cmd;
+        };
+        let var = self.prompt_env_var();
+
+        match (self.pass_input, syntax) {
+            (PassInput::Cmd, _) => cmd,
+            (PassInput::Env, Syntax::Posix) => {
+                format!("(export {}={}; {})", var, shell_quote(input), cmd)
+            }
+            (PassInput::Env, Syntax::Fish) => {
+                format!("begin; set -lx {} {}; {}; end", var, fish_quote(input), cmd)
+            }
+            (PassInput::Stdin, Syntax::Posix) => {
+                format!("printf '%s\\n' {} | {{ {}; }}", shell_quote(input), cmd)
+            }
+            (PassInput::Stdin, Syntax::Fish) => {
+                format!("printf '%s\\n' {} | begin; {}; end", fish_quote(input), cmd)
+            }
+        }
+    }
+
+    // This function fills the command's placeholders with the prompt's input
+    // and the built-in ones from `context`. The prompt wins when they share
+    // a name.
+    pub fn render_cmd(&self, input: Option<&str>, context: &Context) -> String {
+        template::render(&self.cmd, |name| self.lookup(name, input, context))
+    }
+
+    // The command to run, and the same command for the audit log with the
+    // `{env.NAME}` values and a secret prompt's input left out. Both are
+    // filled from the same lookups, so e.g. `{git.branch}` is only asked
+    // for once.
+    pub fn render_cmds(&self, input: Option<&str>, context: &Context) -> (String, String) {
+        let mut values = HashMap::new();
+        let cmd = template::render(&self.cmd, |name| {
+            let value = self.lookup(name, input, context);
+
+            values.insert(name.to_owned(), value.clone());
+            value
+        });
+        let logged_cmd = template::render(&self.cmd, |name| {
+            let value = values.get(name).cloned().flatten()?;
+            let is_secret = name.starts_with("env.")
+                || (self.secret && input.is_some() && self.is_prompt(name));
+
+            Some(if is_secret {
+                REDACTED.to_owned()
+            } else {
+                value
+            })
+        });
+
+        (cmd, logged_cmd)
+    }
+
+    // The values of the `{env.NAME}` placeholders in the command
+    pub fn env_values(&self, context: &Context) -> Vec<String> {
+        let mut values = Vec::new();
+
+        template::render(&self.cmd, |name| {
+            if name.starts_with("env.") {
+                values.extend(context.lookup(name));
+            }
+
+            None
+        });
+
+        values
+    }
+
+    fn is_prompt(&self, name: &str) -> bool {
+        name.is_empty() || name == self.prompt_name()
+    }
+
+    fn lookup(&self, name: &str, input: Option<&str>, context: &Context) -> Option<String> {
+        match input {
+            Some(input) if self.is_prompt(name) => Some(input.to_owned()),
+            _ => context.lookup(name),
+        }
+    }
+}
+
+// Ties keep the order the keymaps were defined in. `frecency` is only asked
+// for with `Order::Frecency`, and once for each keymap.
+pub fn sort(keymaps: &mut [Keymap], order: Order, frecency: impl Fn(&Keymap) -> f64) {
+    match order {
+        Order::Defined => {}
+        Order::Alphabetical => {
+            keymaps.sort_by_cached_key(|keymap| keymap.description.to_lowercase());
+        }
+        Order::Frecency => keymaps.sort_by_cached_key(|keymap| Reverse(Score(frecency(keymap)))),
+    }
+}
+
+// A frecency score that can be used as a sort key
+#[derive(Debug, Clone, Copy)]
+struct Score(f64);
+
+impl PartialEq for Score {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+// This finds a keymap by its name, or by its key when `key_or_name` is a
+// single character
+pub fn find<'a>(keymaps: &'a [Keymap], key_or_name: &str) -> Option<&'a Keymap> {
+    let mut chars = key_or_name.chars();
+    let key = match (chars.next(), chars.next()) {
+        (Some(key), None) => Some(key),
+        _ => None,
+    };
+
+    keymaps
+        .iter()
+        .find(|keymap| keymap.name.as_deref() == Some(key_or_name))
+        .or_else(|| keymaps.iter().find(|keymap| Some(keymap.key) == key))
 }
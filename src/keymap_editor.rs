@@ -0,0 +1,392 @@
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use termion::event::Key;
+use termion::{clear, cursor, style};
+
+use crate::config_file;
+use crate::error::Error;
+use crate::input::{self, Input, InputError};
+use crate::keymap::{self, Keymap};
+use crate::term_writer::TermCursor;
+
+// What a key asks the editor to do, besides moving the selection
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Edit,
+    Add,
+    Delete,
+    MoveUp,
+    MoveDown,
+    Close,
+}
+
+// The palette's keymaps as a list to change, drawn over the whole terminal.
+// Every change is written to the config file the keymap came from right
+// away, and only if the file still loads afterwards.
+pub struct KeymapEditor<'a> {
+    keymaps: &'a mut Vec<Keymap>,
+    selected: usize,
+    // Where added keymaps go
+    new_path: PathBuf,
+    // Shown on the last row, e.g. why a change wasn't saved
+    message: Option<String>,
+    size: (u16, u16),
+}
+
+impl<'a> KeymapEditor<'a> {
+    pub fn new<P: AsRef<Path>>(
+        keymaps: &'a mut Vec<Keymap>,
+        new_path: P,
+        size: (u16, u16),
+    ) -> Self {
+        Self {
+            keymaps,
+            selected: 0,
+            new_path: new_path.as_ref().to_owned(),
+            message: None,
+            size,
+        }
+    }
+
+    pub fn resize(&mut self, size: (u16, u16)) {
+        self.size = size;
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    pub fn key(&mut self, key: Key) -> Option<Action> {
+        let last = self.keymaps.len().saturating_sub(1);
+
+        match key {
+            Key::Char('j') | Key::Down => self.selected = (self.selected + 1).min(last),
+            Key::Char('k') | Key::Up => self.selected = self.selected.saturating_sub(1),
+            Key::Char('\n') | Key::Char('e') => return Some(Action::Edit),
+            Key::Char('a') => return Some(Action::Add),
+            Key::Char('d') => return Some(Action::Delete),
+            Key::Char('K') => return Some(Action::MoveUp),
+            Key::Char('J') => return Some(Action::MoveDown),
+            Key::Char('q') | Key::Esc => return Some(Action::Close),
+            _ => {}
+        }
+
+        None
+    }
+
+    // Replaces the selected keymap with `keymap` (`edit`), or adds it after
+    // the others
+    pub fn save(&mut self, keymap: Keymap, edit: bool) -> Result<(), Error> {
+        let old = edit.then(|| self.keymaps.get(self.selected)).flatten();
+
+        if keymap::is_reserved(keymap.key) {
+            return Err(Error::Config(format!(
+                "`{}` is used by the palette",
+                keymap.key
+            )));
+        }
+
+        if let Some(taken) = self
+            .keymaps
+            .iter()
+            .find(|k| k.key == keymap.key && old.is_none_or(|old| old.key != k.key))
+        {
+            return Err(Error::Config(format!(
+                "`{}` is taken by {}",
+                keymap.key, taken.description
+            )));
+        }
+
+        match old {
+            Some(old) => {
+                let path = source(old)?.to_owned();
+
+                config_file::update_keymap(&path, old.key, &keymap)?;
+                self.keymaps[self.selected] = keymap.with_source(&path);
+                self.message = Some(format!("Saved to {}", path.display()));
+            }
+            None => {
+                config_file::add_keymap(&self.new_path, self.keymaps, keymap)?;
+                self.selected = self.keymaps.len() - 1;
+                self.message = Some(format!("Saved to {}", self.new_path.display()));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn delete(&mut self) -> Result<(), Error> {
+        let Some(keymap) = self.keymaps.get(self.selected) else {
+            return Ok(());
+        };
+        let path = source(keymap)?.to_owned();
+
+        config_file::remove_keymap(&path, keymap.key)?;
+        self.keymaps.remove(self.selected);
+        self.selected = self.selected.min(self.keymaps.len().saturating_sub(1));
+        self.message = Some(format!("Removed from {}", path.display()));
+
+        Ok(())
+    }
+
+    // Keymaps only move within their own file
+    pub fn move_selected(&mut self, up: bool) -> Result<(), Error> {
+        let other = if up {
+            self.selected.checked_sub(1)
+        } else {
+            Some(self.selected + 1)
+        };
+        let Some(other) = other.filter(|&other| other < self.keymaps.len()) else {
+            return Ok(());
+        };
+        let (keymap, other_keymap) = (&self.keymaps[self.selected], &self.keymaps[other]);
+        let path = source(keymap)?;
+
+        if other_keymap.source.as_deref() != Some(path) {
+            return Err(Error::Config(format!(
+                "`{}` is in another file",
+                other_keymap.key
+            )));
+        }
+
+        config_file::swap_keymaps(path, keymap.key, other_keymap.key)?;
+        self.keymaps.swap(self.selected, other);
+        self.selected = other;
+        self.message = None;
+
+        Ok(())
+    }
+
+    pub fn render<T: TermCursor + Write>(&self, stdout: &mut T) -> Result<(), Error> {
+        let (cols, rows) = self.size;
+        // The title and the message take a row each
+        let height = usize::from(rows.saturating_sub(2)).max(1);
+        let top = self.selected.saturating_sub(height - 1);
+
+        stdout.write_term(format_args!(
+            "{}{}{}Keymaps (Enter edit, a add, d delete, J/K move, q){}",
+            clear::All,
+            cursor::Hide,
+            cursor::Goto(1, 1),
+            style::Reset
+        ))?;
+
+        for (row, (i, keymap)) in self
+            .keymaps
+            .iter()
+            .enumerate()
+            .skip(top)
+            .take(height)
+            .enumerate()
+        {
+            let mut source = match &keymap.source {
+                Some(path) => path.display().to_string(),
+                None => "built in".to_owned(),
+            };
+
+            // Its `when` isn't met, so the palette doesn't show it
+            if !keymap.is_available() {
+                source.push_str(", not offered here");
+            }
+            let text = format!("{}  {}  ({})", keymap.key, keymap.description, source);
+            let text: String = text.chars().take(cols.into()).collect();
+
+            stdout.write_term(format_args!("{}", cursor::Goto(1, row as u16 + 2)))?;
+
+            if i == self.selected {
+                stdout.write_term(format_args!("{}{}{}", style::Invert, text, style::Reset))?;
+            } else {
+                stdout.write_term(format_args!("{}", text))?;
+            }
+        }
+
+        if let Some(message) = &self.message {
+            let message: String = message.chars().take(cols.into()).collect();
+
+            stdout.write_term(format_args!("{}{}", cursor::Goto(1, rows), message))?;
+        }
+
+        stdout.flush()?;
+
+        Ok(())
+    }
+
+    // This shows the editor until `q` is pressed, with `size` asked for the
+    // terminal's size before each redraw and before a field is asked for
+    pub fn run<T: TermCursor + Write>(
+        &mut self,
+        stdout: &mut T,
+        mut keys: impl Iterator<Item = Result<Key, io::Error>>,
+        size: impl Fn() -> (u16, u16),
+    ) -> Result<(), Error> {
+        self.resize(size());
+        self.render(stdout)?;
+
+        while let Some(key) = keys.next() {
+            let action = self.key(key?);
+
+            self.resize(size());
+
+            if action.is_some() {
+                self.message = None;
+            }
+
+            let result = match action {
+                None => Ok(()),
+                Some(Action::Close) => break,
+                Some(Action::Edit) => match self.keymaps.get(self.selected).cloned() {
+                    Some(keymap) => self.ask(stdout, &mut keys, &keymap, true),
+                    None => Ok(()),
+                },
+                Some(Action::Add) => self.ask(stdout, &mut keys, &Keymap::default(), false),
+                Some(Action::Delete) => self.confirm_delete(stdout, &mut keys),
+                Some(Action::MoveUp) => self.move_selected(true),
+                Some(Action::MoveDown) => self.move_selected(false),
+            };
+
+            // Mistakes are shown and the keymap is left as it was
+            if let Err(e) = result {
+                match e {
+                    Error::Config(message) => self.message = Some(message),
+                    e => return Err(e),
+                }
+            }
+
+            self.resize(size());
+            self.render(stdout)?;
+        }
+
+        stdout.write_term(format_args!("{}", cursor::Show))?;
+
+        Ok(())
+    }
+
+    // Goes through the fields most often changed, with the rest left to the
+    // file. Esc on any of them leaves the keymap as it was.
+    fn ask<T: TermCursor + Write>(
+        &mut self,
+        stdout: &mut T,
+        keys: &mut impl Iterator<Item = Result<Key, io::Error>>,
+        keymap: &Keymap,
+        edit: bool,
+    ) -> Result<(), Error> {
+        if edit {
+            source(keymap)?;
+        }
+
+        let key = if edit {
+            keymap.key.to_string()
+        } else {
+            String::new()
+        };
+        let Some(key) = self.field(stdout, keys, "Key:", &key)? else {
+            return Ok(());
+        };
+        let mut chars = key.chars();
+        let key = match (chars.next(), chars.next()) {
+            (Some(key), None) => key,
+            _ => return Err(Error::Config(format!("`{}` isn't a single key", key))),
+        };
+        let Some(cmd) = self.field(stdout, keys, "Command:", &keymap.cmd)? else {
+            return Ok(());
+        };
+
+        if cmd.trim().is_empty() {
+            return Err(Error::Config("the command can't be empty".to_owned()));
+        }
+
+        let description = if keymap.description == keymap.cmd {
+            ""
+        } else {
+            keymap.description.as_str()
+        };
+        let Some(description) = self.field(stdout, keys, "Description (optional):", description)?
+        else {
+            return Ok(());
+        };
+        let prompt = keymap.prompt.as_deref().unwrap_or("");
+        let Some(prompt) = self.field(stdout, keys, "Prompt (optional):", prompt)? else {
+            return Ok(());
+        };
+        let new = Keymap {
+            key,
+            cmd: cmd.clone(),
+            description: if description.is_empty() {
+                cmd.clone()
+            } else {
+                description
+            },
+            prompt: (!prompt.is_empty()).then_some(prompt),
+            ..keymap.clone()
+        };
+
+        self.save(new, edit)
+    }
+
+    // `None` when Esc is pressed
+    fn field<T: TermCursor + Write>(
+        &self,
+        stdout: &mut T,
+        keys: &mut impl Iterator<Item = Result<Key, io::Error>>,
+        label: &str,
+        value: &str,
+    ) -> Result<Option<String>, Error> {
+        let rows = self.size.1;
+
+        stdout.write_term(format_args!(
+            "{}{}{}{}{}{}",
+            cursor::Goto(1, rows.saturating_sub(1)),
+            clear::CurrentLine,
+            label,
+            cursor::Goto(1, rows),
+            clear::CurrentLine,
+            cursor::Show
+        ))?;
+        stdout.flush()?;
+
+        match input::edit_from_keys(value.to_owned(), keys, stdout) {
+            Ok(Input::Text(text)) => Ok(Some(text.trim().to_owned())),
+            Ok(_) => Ok(None),
+            Err(InputError::EmptyString) => Ok(Some(String::new())),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn confirm_delete<T: TermCursor + Write>(
+        &mut self,
+        stdout: &mut T,
+        keys: &mut impl Iterator<Item = Result<Key, io::Error>>,
+    ) -> Result<(), Error> {
+        let Some(keymap) = self.keymaps.get(self.selected) else {
+            return Ok(());
+        };
+
+        source(keymap)?;
+        stdout.write_term(format_args!(
+            "{}{}Delete `{}`? (y/n)",
+            cursor::Goto(1, self.size.1),
+            clear::CurrentLine,
+            keymap.key
+        ))?;
+        stdout.flush()?;
+
+        match keys.next().transpose()? {
+            Some(Key::Char('y')) => self.delete(),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn source(keymap: &Keymap) -> Result<&Path, Error> {
+    keymap.source.as_deref().ok_or_else(|| {
+        Error::Config(format!(
+            "`{}` is built in, add keymaps to a config file to change them",
+            keymap.key
+        ))
+    })
+}
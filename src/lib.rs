@@ -1,9 +1,28 @@
+pub mod audit;
+pub mod cli;
 pub mod cmd_runner;
+pub mod config;
+pub mod config_file;
+pub mod context;
+pub mod error;
 pub mod input;
 pub mod keymap;
+pub mod keymap_editor;
 pub mod mock_stdout;
+pub mod output;
+pub mod pager;
+pub mod pty;
+pub mod quickfix;
 pub mod raw_stdout;
 pub mod screen;
+pub mod shell;
+pub mod shell_init;
+pub mod stats;
 pub mod step;
+pub mod supervisor;
+pub mod template;
 pub mod term_writer;
+pub mod terminal;
 pub mod utils;
+
+pub use error::Error;
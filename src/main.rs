@@ -1,53 +1,544 @@
-use quicommand::keymap::Keymap;
+use chrono::Local;
+use quicommand::audit::{AuditLog, Filter, Frecency, Record};
+use quicommand::cli::{self, Command, ListFormat, Print};
+use quicommand::cmd_runner::OutputMode;
+use quicommand::config::Config;
+use quicommand::config_file;
+use quicommand::input::{Input, InputError};
+use quicommand::keymap::{self, Keymap, Order};
+use quicommand::keymap_editor::KeymapEditor;
+use quicommand::output::Output;
+use quicommand::pager::Pager;
+use quicommand::quickfix::{self, Location, Picker};
 use quicommand::raw_stdout::RawStdout;
 use quicommand::screen::Screen;
-use quicommand::step::Step;
-use std::io::stdin;
-use std::io::Write;
+use quicommand::shell_init;
+use quicommand::stats::Stats;
+use quicommand::step::{self, Process, Step};
+use quicommand::terminal::TerminalGuard;
+use quicommand::Error;
+use std::env;
+use std::fs::File;
+use std::io::{self, stdin, IsTerminal, Write};
+use std::mem::ManuallyDrop;
+use std::os::fd::FromRawFd;
+use std::path::PathBuf;
+use std::process;
 use termion::event::Key;
 use termion::input::TermRead;
 
 fn main() {
-    let stdout = RawStdout::new().unwrap();
-    let screen = Screen::new(stdout);
-    let mut step = Step::new(screen);
+    let args: Vec<String> = env::args().skip(1).collect();
 
-    step.screen.stdout.flush().unwrap();
+    match run(&args) {
+        Ok(exit_code) => process::exit(exit_code),
+        Err(e) => {
+            eprintln!("quicommand: {}", e);
+            process::exit(e.exit_code());
+        }
+    }
+}
 
-    let keymaps = vec![
+// Used when no config file has any keymaps
+fn default_keymaps() -> Vec<Keymap> {
+    vec![
         Keymap::new('c', "git add . && git commit -m \"{}\"")
+            .with_name("commit")
             .with_description("Git commit")
-            .with_prompt("Enter commit message:"),
-        Keymap::new('m', "hx src/main.*"),
-        Keymap::new('n', "node script.*"),
-        Keymap::new('p', "git push -u origin main"),
-        Keymap::new('b', "cargo build --release"),
+            .with_prompt("Enter commit message:")
+            .with_prompt_name("message"),
+        Keymap::new('m', "hx src/main.*").with_name("edit"),
+        Keymap::new('n', "node script.*").with_name("node"),
+        Keymap::new('p', "git push -u origin {git.branch}").with_name("push"),
+        Keymap::new('b', "cargo build --release").with_name("build"),
         Keymap::new(
             't',
             "git log --author=\"Alex\" --since=\"midnight\" --no-merges --oneline | wc -l",
-        ),
-    ];
+        )
+        .with_name("commits-today"),
+    ]
+}
 
-    step.show_select_cmd(&keymaps);
-    // screen.stdout.flush().unwrap();
+// This returns the exit code of the (last) command that was run. The config
+// files are only read for the commands that need them, so a broken one
+// doesn't get in the way of `log` or `shell-init`.
+fn run(args: &[String]) -> Result<i32, Error> {
+    match cli::parse(args)? {
+        Command::Palette {
+            pager,
+            edit,
+            dry_run,
+            print,
+        } => {
+            let (mut config, keymaps) = load_config()?;
 
-    for key in stdin().keys() {
-        match key.unwrap() {
-            Key::Char('q') => {
-                step.screen.show_cursor();
-                break;
+            if pager {
+                config = config.with_pager(true);
+            }
+
+            if edit {
+                config = config.with_edit(true);
             }
-            Key::Char(key) => {
-                let Some(keymap) = keymaps.iter().find(|k| k.key == key) else {
-                    continue;
-                };
 
+            if dry_run {
+                config = config.with_dry_run(true);
+            }
+
+            let records = match (&config.audit_log, config.order) {
+                (Some(audit_log), Order::Frecency) => {
+                    audit_log.records(&Filter::default()).unwrap_or_default()
+                }
+                _ => Vec::new(),
+            };
+
+            match print {
+                Some(print) => {
+                    let keymaps = shown_keymaps(&config, &keymaps, &records);
+
+                    print_cmd(config, &keymaps, print)
+                }
+                None => palette(config, keymaps, &records),
+            }
+        }
+        Command::ShellInit { shell } => {
+            print!("{}", shell_init::script(&shell)?);
+
+            Ok(0)
+        }
+        Command::Run { target, args } => {
+            let (config, keymaps) = load_config()?;
+
+            run_keymap(config, &keymaps, &target, &args)
+        }
+        Command::List { format } => list(&load_config()?.1, format),
+        Command::Log { filter, json } => log(&filter, json),
+        Command::Stats => stats(),
+    }
+}
+
+// The settings and keymaps from the config files, with the built-in keymaps
+// when none of them has any
+fn load_config() -> Result<(Config, Vec<Keymap>), Error> {
+    let files = config_file::load_all()?;
+    let mut keymaps = config_file::merge_keymaps(&files);
+    let mut config = files
+        .iter()
+        .fold(Config::default(), |config, file| file.apply(config));
+
+    if keymaps.is_empty() {
+        keymaps = default_keymaps();
+    }
+
+    if let Some(path) = AuditLog::default_path() {
+        config = config.with_audit_log(AuditLog::new(path));
+    }
+
+    Ok((config, keymaps))
+}
+
+// The keymaps the palette offers, which are the available ones in the
+// configured order. `records` is only needed for `Order::Frecency`.
+fn shown_keymaps(config: &Config, keymaps: &[Keymap], records: &[Record]) -> Vec<Keymap> {
+    let mut keymaps: Vec<Keymap> = keymaps
+        .iter()
+        .filter(|keymap| keymap.is_available())
+        .cloned()
+        .collect();
+    let frecency = Frecency::new(records, Local::now());
+
+    keymap::sort(&mut keymaps, config.order, |keymap| frecency.score(keymap));
+
+    keymaps
+}
+
+// Runs a keymap without the palette. The command's output goes straight
+// through when it isn't a terminal, so it can be piped.
+fn run_keymap(
+    config: Config,
+    keymaps: &[Keymap],
+    target: &str,
+    args: &[(String, String)],
+) -> Result<i32, Error> {
+    let keymap = keymap::find(keymaps, target)
+        .ok_or_else(|| Error::Usage(format!("no keymap `{}`", target)))?;
+
+    if let Some(condition) = keymap.conditions.iter().find(|c| !c.is_met()) {
+        return Err(Error::Usage(format!(
+            "`{}` isn't available here ({})",
+            target, condition
+        )));
+    }
+
+    let input = cli::keymap_input(keymap, args)?;
+    let is_terminal = io::stdout().is_terminal();
+    let output_mode = if is_terminal {
+        OutputMode::Pty
+    } else {
+        OutputMode::Pipe
+    };
+    let config = config
+        .with_output_mode(output_mode)
+        .with_show_status(is_terminal);
+    let process = step::run_keymap(&config, keymap, input.as_deref())?;
+
+    Ok(process.exit_code())
+}
+
+// The text format only shows what the palette would
+fn list(keymaps: &[Keymap], format: ListFormat) -> Result<i32, Error> {
+    if format == ListFormat::Json {
+        println!("{:#}", cli::keymaps_json(keymaps));
+
+        return Ok(0);
+    }
+
+    let keymaps: Vec<&Keymap> = keymaps.iter().filter(|k| k.is_available()).collect();
+    let width = keymaps
+        .iter()
+        .filter_map(|keymap| keymap.name.as_ref())
+        .map(|name| name.chars().count())
+        .max()
+        .unwrap_or(0);
+
+    for keymap in keymaps {
+        println!(
+            "{}  {:<width$}  {}",
+            keymap.key,
+            keymap.name.as_deref().unwrap_or(""),
+            keymap.description,
+            width = width
+        );
+    }
+
+    Ok(0)
+}
+
+// Shows the palette until `q` is pressed or a command is done with.
+// `keymaps` are all of them, including the ones that aren't offered here,
+// in the order they're defined in.
+fn palette(mut config: Config, mut keymaps: Vec<Keymap>, records: &[Record]) -> Result<i32, Error> {
+    // Declared first so it's dropped last, after `RawStdout`
+    let _terminal_guard = TerminalGuard::new()?;
+    let mut exit_code = 0;
+
+    loop {
+        let stdout = RawStdout::new()?;
+        let screen = Screen::new(stdout);
+        let mut step = Step::new(screen).with_config(config.clone());
+        let shown = shown_keymaps(&config, &keymaps, records);
+
+        step.screen.stdout.flush()?;
+        step.show_select_cmd(&shown)?;
+
+        let mut ad_hoc_cmd = None;
+        let process = match select_keymap(&mut step, &shown)? {
+            Selection::Keymap { keymap, edit } => {
                 let input = step.input_from_prompt(keymap.prompt.as_deref(), stdin().keys());
 
-                step.process_input(input, keymap).unwrap();
+                if edit || config.edit {
+                    let text = match &input {
+                        Ok(Input::Text(text)) => Some(text.clone()),
+                        _ => None,
+                    };
+                    let cmd = step.edit_cmd(input, keymap, stdin().keys());
+
+                    step.process_edited(cmd, keymap, text.as_deref())?
+                } else {
+                    step.process_input(input, keymap)?
+                }
+            }
+            Selection::AdHoc => {
+                let history = step::ad_hoc_history(&config);
+                let input = step.input_ad_hoc(&history, stdin().keys());
+
+                if let Ok(Input::Text(cmd)) = &input {
+                    ad_hoc_cmd = Some(cmd.clone());
+                }
+
+                let keymap = Keymap::ad_hoc(ad_hoc_cmd.as_deref().unwrap_or_default());
+
+                step.process_edited(input, &keymap, None)?
+            }
+            Selection::ToggleDryRun => {
+                config.dry_run = !config.dry_run;
+                continue;
+            }
+            // Unsorted, so J/K move a keymap past the one next to it in
+            // its file
+            Selection::EditKeymaps => {
+                edit_keymaps(&mut keymaps)?;
+                continue;
+            }
+            Selection::Quit => return Ok(exit_code),
+        };
+
+        exit_code = process.exit_code();
+
+        if let Some(output) = process.output() {
+            config.context = config.context.with_last_output(output);
+        }
+
+        // Dry runs and cancelled command lines have nothing worth keeping
+        if let (Some(cmd), false) = (&ad_hoc_cmd, process == Process::Exit) {
+            offer_to_save(&mut keymaps, cmd)?;
+        }
+
+        // Interactive commands have nothing captured to look at
+        let Some(output) = process
+            .output()
+            .filter(|output| !output.combined.is_empty())
+        else {
+            return Ok(exit_code);
+        };
+        let locations = if config.quickfix && !process.success() {
+            quickfix::locations(output)
+        } else {
+            Vec::new()
+        };
+
+        if let Some(location) = pick_location(&locations)? {
+            step::edit(&config, location)?;
+        } else if config.pager {
+            show_pager(output)?;
+        } else {
+            return Ok(exit_code);
+        }
+    }
+}
+
+// Goes through the palette like `palette` does, but writes the command to
+// `print.fd` instead of running it. Cancelling exits with 130, like Ctrl-C
+// would.
+fn print_cmd(config: Config, keymaps: &[Keymap], print: Print) -> Result<i32, Error> {
+    // Declared first so it's dropped last, after `RawStdout`
+    let _terminal_guard = TerminalGuard::new()?;
+    let mut screen = Screen::new(RawStdout::new()?);
+
+    // So the shell's screen is left as it was
+    screen.enter_alternate_screen()?;
+
+    let mut step = Step::new(screen).with_config(config);
+
+    step.show_select_cmd(keymaps)?;
+
+    // Nothing is run here, and the command can be edited on the shell's
+    // line anyway
+    let selection = loop {
+        match select_keymap(&mut step, keymaps)? {
+            Selection::ToggleDryRun | Selection::AdHoc | Selection::EditKeymaps => {}
+            selection => break selection,
+        }
+    };
+    let cmd = match selection {
+        Selection::Keymap { keymap, .. } => {
+            let input = step.input_from_prompt(keymap.prompt.as_deref(), stdin().keys());
+
+            step.resolve_input(input, keymap, print.syntax)
+        }
+        _ => Ok(None),
+    };
+
+    step.screen.leave_alternate_screen()?;
+    step.screen.show_cursor()?;
+    drop(step);
+
+    let Some(cmd) = cmd? else {
+        return Ok(130);
+    };
+    // Not ours to close
+    let mut out = ManuallyDrop::new(unsafe { File::from_raw_fd(print.fd) });
+
+    writeln!(out, "{}", cmd)?;
+
+    Ok(0)
+}
+
+// This returns `None` when there's nothing to pick or `q` is pressed
+fn pick_location(locations: &[Location]) -> Result<Option<&Location>, Error> {
+    if locations.is_empty() {
+        return Ok(None);
+    }
+
+    let size = termion::terminal_size().unwrap_or((80, 24));
+    let mut screen = Screen::new(RawStdout::new()?);
+
+    screen.enter_alternate_screen()?;
+
+    let location = Picker::new(locations, size).run(&mut screen.stdout, stdin().keys())?;
+
+    screen.leave_alternate_screen()?;
+    screen.show_cursor()?;
+
+    Ok(location)
+}
+
+// What was pressed in the palette
+enum Selection<'a> {
+    // Holding Alt with the key asks for the command to be edited before
+    // it's run
+    Keymap { keymap: &'a Keymap, edit: bool },
+    // `:`, for a command that isn't bound to a key
+    AdHoc,
+    // Ctrl-D
+    ToggleDryRun,
+    // Ctrl-E
+    EditKeymaps,
+    // `q`, or the end of the input
+    Quit,
+}
+
+fn select_keymap<'a>(
+    step: &mut Step<RawStdout>,
+    keymaps: &'a [Keymap],
+) -> Result<Selection<'a>, Error> {
+    for key in stdin().keys() {
+        let (key, edit) = match key? {
+            Key::Char('q') => break,
+            Key::Char(':') => return Ok(Selection::AdHoc),
+            Key::Ctrl('d') => return Ok(Selection::ToggleDryRun),
+            Key::Ctrl('e') => return Ok(Selection::EditKeymaps),
+            Key::Char(key) => (key, false),
+            Key::Alt(key) => (key, true),
+            _ => continue,
+        };
+
+        if let Some(keymap) = keymaps.iter().find(|k| k.key == key) {
+            return Ok(Selection::Keymap { keymap, edit });
+        }
+    }
+
+    step.screen.show_cursor()?;
+
+    Ok(Selection::Quit)
+}
+
+// Asks whether an ad-hoc command should get a key, and if so adds it to the
+// project's config (creating `.quicommand.toml` when there's none yet)
+fn offer_to_save(keymaps: &mut Vec<Keymap>, cmd: &str) -> Result<(), Error> {
+    let mut step = Step::new(Screen::new(RawStdout::new()?));
+
+    step.screen.show_prompt("Save as keymap? (y/n)")?;
+    step.screen.stdout.flush()?;
+
+    if !matches!(stdin().keys().next().transpose()?, Some(Key::Char('y'))) {
+        return Ok(());
+    }
+
+    step.screen.show_prompt("Key:")?;
+    step.screen.stdout.flush()?;
+
+    let mut key = None;
+
+    for k in stdin().keys() {
+        match k? {
+            Key::Esc => return Ok(()),
+            Key::Char(c) if keymap::is_reserved(c) || keymaps.iter().any(|k| k.key == c) => {
+                step.screen
+                    .show_prompt(&format!("`{}` is taken, try another key:", c))?;
+                step.screen.stdout.flush()?;
+            }
+            Key::Char(c) if !c.is_whitespace() => {
+                key = Some(c);
                 break;
             }
             _ => {}
         }
     }
+
+    let Some(key) = key else {
+        return Ok(());
+    };
+    let description = match step.input_from_prompt(Some("Description:"), stdin().keys()) {
+        Ok(Input::Text(description)) => Some(description),
+        // An empty description means the command is shown instead
+        Err(Error::Input(InputError::EmptyString)) => None,
+        Ok(_) => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    let path = new_keymaps_path()?;
+    let mut keymap = Keymap::new(key, cmd);
+
+    if let Some(description) = description {
+        keymap = keymap.with_description(description);
+    }
+
+    config_file::add_keymap(&path, keymaps, keymap)?;
+    step.screen.add_newline()?;
+    step.screen
+        .show_prompt(&format!("Saved to {}", path.display()))?;
+
+    Ok(())
+}
+
+// The project's config, which is created in the working directory when
+// there's none yet
+fn new_keymaps_path() -> Result<PathBuf, Error> {
+    let dir = env::current_dir()?;
+
+    Ok(config_file::project_path(&dir).unwrap_or_else(|| dir.join(config_file::PROJECT_FILE_NAME)))
+}
+
+fn edit_keymaps(keymaps: &mut Vec<Keymap>) -> Result<(), Error> {
+    let size = || termion::terminal_size().unwrap_or((80, 24));
+    let mut screen = Screen::new(RawStdout::new()?);
+    let mut editor = KeymapEditor::new(keymaps, new_keymaps_path()?, size());
+
+    screen.enter_alternate_screen()?;
+    editor.run(&mut screen.stdout, stdin().keys(), size)?;
+    screen.leave_alternate_screen()?;
+
+    Ok(())
+}
+
+fn show_pager(output: &Output) -> Result<(), Error> {
+    let size = || termion::terminal_size().unwrap_or((80, 24));
+    let mut screen = Screen::new(RawStdout::new()?);
+    let mut pager = Pager::new(output, size());
+
+    screen.enter_alternate_screen()?;
+    pager.run(&mut screen.stdout, stdin().keys(), size)?;
+    screen.leave_alternate_screen()?;
+
+    Ok(())
+}
+
+// Sums up the audit log, including keymaps that were never run. Those are
+// left out (with a warning) when the config files can't be read, since the
+// log doesn't depend on them.
+fn stats() -> Result<i32, Error> {
+    let records = audit_log()?.records(&Filter::default())?;
+    let keymaps = match load_config() {
+        Ok((_, keymaps)) => keymaps,
+        Err(e) => {
+            eprintln!("quicommand: {}", e);
+            Vec::new()
+        }
+    };
+
+    print!("{}", Stats::new(&records, &keymaps));
+
+    Ok(0)
+}
+
+// Lists the commands that were run, oldest first
+fn log(filter: &Filter, json: bool) -> Result<i32, Error> {
+    for record in audit_log()?.records(filter)? {
+        if json {
+            println!(
+                "{}",
+                serde_json::to_string(&record).map_err(io::Error::from)?
+            );
+        } else {
+            println!("{}", record);
+        }
+    }
+
+    Ok(0)
+}
+
+fn audit_log() -> Result<AuditLog, Error> {
+    let path = AuditLog::default_path()
+        .ok_or_else(|| Error::Config("can't find the audit log without $HOME".to_owned()))?;
+
+    Ok(AuditLog::new(path))
 }
@@ -18,6 +18,12 @@ impl MockStdout {
     }
 }
 
+impl Default for MockStdout {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Write for MockStdout {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
         self.buffer.write(buf)
@@ -36,9 +42,7 @@ impl TermCursor for MockStdout {
             self.cursor_pos.0 += 1;
         }
 
-        self.write_fmt(fmt).unwrap();
-
-        Ok(())
+        self.write_fmt(fmt)
     }
 
     fn get_cursor_pos(&mut self) -> Result<(u16, u16), std::io::Error> {
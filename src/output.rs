@@ -0,0 +1,273 @@
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{env, fmt, process};
+
+use crate::error::Error;
+
+// How much output is kept in memory before the rest goes to a file
+pub const DEFAULT_MEMORY_CAP: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+// What a running command does, as it happens
+#[derive(Debug, Clone, PartialEq)]
+pub enum OutputEvent {
+    Stdout(Vec<u8>),
+    Stderr(Vec<u8>),
+    Exit(ExitStatus),
+}
+
+// Bytes as the command wrote them, and when we read them (since it started)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+    pub stream: Stream,
+    pub at: Duration,
+    pub bytes: Vec<u8>,
+}
+
+// Not `Clone`, since the spill file goes away with the output that has it
+#[derive(Debug, PartialEq)]
+pub struct Output {
+    pub status: ExitStatus,
+    // Both streams, in the order they were read. This is the only copy of
+    // the bytes, so the memory cap holds (see `stdout` and `stderr`).
+    pub combined: Vec<Chunk>,
+    // Everything the command wrote, when it didn't all fit in memory (the
+    // chunks above are then only the most recent part). It's removed when
+    // the output is dropped.
+    pub spill_file: Option<PathBuf>,
+}
+
+// How much of a command's output is kept: all of it, or only the last
+// lines (`tail:200`)
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Retention {
+    #[default]
+    All,
+    Tail(usize),
+}
+
+impl FromStr for Retention {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::Config(format!("invalid output setting `{}`", s));
+
+        match s.split_once(':') {
+            None if s == "all" => Ok(Retention::All),
+            Some(("tail", lines)) => lines.parse().map(Retention::Tail).map_err(|_| invalid()),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for Retention {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Retention::All => write!(f, "all"),
+            Retention::Tail(lines) => write!(f, "tail:{}", lines),
+        }
+    }
+}
+
+impl Output {
+    pub fn new(status: ExitStatus, combined: Vec<Chunk>) -> Self {
+        Self {
+            status,
+            combined,
+            spill_file: None,
+        }
+    }
+
+    // One stream on its own, put together from `combined` when asked for
+    pub fn stdout(&self) -> Vec<u8> {
+        self.stream_bytes(Stream::Stdout)
+    }
+
+    pub fn stderr(&self) -> Vec<u8> {
+        self.stream_bytes(Stream::Stderr)
+    }
+
+    // Both streams interleaved the way they appeared on the terminal
+    pub fn combined_bytes(&self) -> Vec<u8> {
+        self.combined
+            .iter()
+            .flat_map(|chunk| chunk.bytes.iter().copied())
+            .collect()
+    }
+
+    fn stream_bytes(&self, stream: Stream) -> Vec<u8> {
+        self.combined
+            .iter()
+            .filter(|chunk| chunk.stream == stream)
+            .flat_map(|chunk| chunk.bytes.iter().copied())
+            .collect()
+    }
+
+    pub fn ends_with_newline(&self) -> bool {
+        self.combined
+            .last()
+            .is_none_or(|chunk| chunk.bytes.ends_with(b"\n"))
+    }
+}
+
+impl Drop for Output {
+    fn drop(&mut self) {
+        if let Some(path) = &self.spill_file {
+            // A file someone else already removed is fine
+            fs::remove_file(path).ok();
+        }
+    }
+}
+
+// Interactive commands write to the terminal directly, so there's nothing
+// captured besides the status
+impl From<std::process::Output> for Output {
+    fn from(output: std::process::Output) -> Self {
+        let mut combined = Vec::new();
+
+        if !output.stdout.is_empty() {
+            combined.push(Chunk {
+                stream: Stream::Stdout,
+                at: Duration::ZERO,
+                bytes: output.stdout,
+            });
+        }
+
+        if !output.stderr.is_empty() {
+            combined.push(Chunk {
+                stream: Stream::Stderr,
+                at: Duration::ZERO,
+                bytes: output.stderr,
+            });
+        }
+
+        Output::new(output.status, combined)
+    }
+}
+
+// This collects a command's output, keeping at most `memory_cap` bytes (and
+// with `Retention::Tail`, lines) of the most recent chunks. With
+// `Retention::All`, the output is written to a temporary file as soon as it
+// outgrows the cap, so none of it is lost.
+pub struct OutputBuffer {
+    chunks: VecDeque<Chunk>,
+    len: usize,
+    lines: usize,
+    memory_cap: usize,
+    retention: Retention,
+    spill: Option<(PathBuf, File)>,
+}
+
+impl OutputBuffer {
+    pub fn new(memory_cap: usize, retention: Retention) -> Self {
+        Self {
+            chunks: VecDeque::new(),
+            len: 0,
+            lines: 0,
+            memory_cap,
+            retention,
+            spill: None,
+        }
+    }
+
+    pub fn push(&mut self, chunk: Chunk) -> io::Result<()> {
+        if let Some((_, file)) = &mut self.spill {
+            file.write_all(&chunk.bytes)?;
+        }
+
+        self.len += chunk.bytes.len();
+        self.lines += count_lines(&chunk.bytes);
+        self.chunks.push_back(chunk);
+
+        if self.len > self.memory_cap && self.retention == Retention::All && self.spill.is_none() {
+            self.spill = Some(self.spill_file()?);
+        }
+
+        self.trim();
+
+        Ok(())
+    }
+
+    pub fn finish(self, status: ExitStatus) -> io::Result<Output> {
+        let mut output = Output::new(status, self.chunks.into());
+
+        if let Some((path, mut file)) = self.spill {
+            file.flush()?;
+            output.spill_file = Some(path);
+        }
+
+        Ok(output)
+    }
+
+    // The new file starts with everything that's still in memory, which at
+    // this point is everything
+    fn spill_file(&self) -> io::Result<(PathBuf, File)> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_nanos());
+        let path = env::temp_dir().join(format!("quicommand-{}-{}.log", process::id(), nanos));
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+
+        for chunk in &self.chunks {
+            file.write_all(&chunk.bytes)?;
+        }
+
+        Ok((path, file))
+    }
+
+    fn trim(&mut self) {
+        let max_lines = match self.retention {
+            Retention::All => usize::MAX,
+            Retention::Tail(lines) => lines,
+        };
+
+        while self.len > self.memory_cap || self.lines > max_lines {
+            let Some(chunk) = self.chunks.front_mut() else {
+                break;
+            };
+            let excess_lines = self.lines.saturating_sub(max_lines);
+            let excess_len = self.len.saturating_sub(self.memory_cap);
+            // Cut after the last extra line, or just enough bytes
+            let cut = match nth_line_end(&chunk.bytes, excess_lines) {
+                Some(end) => end.max(excess_len.min(chunk.bytes.len())),
+                None if excess_lines > 0 => chunk.bytes.len(),
+                None => excess_len.min(chunk.bytes.len()),
+            };
+            let removed: Vec<u8> = chunk.bytes.drain(..cut).collect();
+
+            self.len -= removed.len();
+            self.lines -= count_lines(&removed);
+
+            if chunk.bytes.is_empty() {
+                self.chunks.pop_front();
+            }
+        }
+    }
+}
+
+fn count_lines(bytes: &[u8]) -> usize {
+    bytes.iter().filter(|&&byte| byte == b'\n').count()
+}
+
+// Where the `n`th line ends (just past its `\n`)
+fn nth_line_end(bytes: &[u8], n: usize) -> Option<usize> {
+    bytes
+        .iter()
+        .enumerate()
+        .filter(|(_, &byte)| byte == b'\n')
+        .nth(n.checked_sub(1)?)
+        .map(|(i, _)| i + 1)
+}
@@ -0,0 +1,338 @@
+use std::io::{self, Write};
+use std::sync::OnceLock;
+
+use regex::Regex;
+use termion::event::Key;
+use termion::{clear, color, cursor, style};
+
+use crate::error::Error;
+use crate::output::{Output, Stream};
+use crate::term_writer::TermCursor;
+
+const TAB_WIDTH: usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    pub stream: Stream,
+    pub text: String,
+}
+
+// What keys do right now
+#[derive(Debug, Clone, PartialEq)]
+enum Mode {
+    Scroll,
+    // The query typed after `/` so far
+    Search(String),
+}
+
+// A scrollable view over a command's captured output, drawn over the whole
+// terminal (`size` is its columns and rows)
+pub struct Pager {
+    lines: Vec<Line>,
+    show_stderr: bool,
+    // The first line shown, counting only visible lines
+    top: usize,
+    query: Option<String>,
+    // The visible line `n` and `N` go on from, once something was found
+    current_match: Option<usize>,
+    mode: Mode,
+    size: (u16, u16),
+}
+
+impl Pager {
+    pub fn new(output: &Output, size: (u16, u16)) -> Self {
+        Self {
+            lines: lines(output),
+            show_stderr: true,
+            top: 0,
+            query: None,
+            current_match: None,
+            mode: Mode::Scroll,
+            size,
+        }
+    }
+
+    pub fn resize(&mut self, size: (u16, u16)) {
+        self.size = size;
+        self.top = self.top.min(self.max_top());
+    }
+
+    pub fn top(&self) -> usize {
+        self.top
+    }
+
+    pub fn current_match(&self) -> Option<usize> {
+        self.current_match
+    }
+
+    pub fn visible_lines(&self) -> Vec<&Line> {
+        self.lines
+            .iter()
+            .filter(|line| self.show_stderr || line.stream == Stream::Stdout)
+            .collect()
+    }
+
+    // This returns false once the pager should be closed
+    pub fn key(&mut self, key: Key) -> bool {
+        if let Mode::Search(query) = &mut self.mode {
+            match key {
+                Key::Char('\n') => {
+                    let query = std::mem::take(query);
+
+                    self.mode = Mode::Scroll;
+
+                    if !query.is_empty() {
+                        self.query = Some(query);
+                        self.current_match = None;
+                        self.find(self.top, true);
+                    }
+                }
+                Key::Esc => self.mode = Mode::Scroll,
+                Key::Backspace => {
+                    query.pop();
+                }
+                Key::Char(c) => query.push(c),
+                _ => {}
+            }
+
+            return true;
+        }
+
+        match key {
+            Key::Char('q') | Key::Esc => return false,
+            Key::Char('j') | Key::Down => self.scroll_to(self.top.saturating_add(1)),
+            Key::Char('k') | Key::Up => self.scroll_to(self.top.saturating_sub(1)),
+            Key::PageDown | Key::Char(' ') => self.scroll_to(self.top + self.page_height()),
+            Key::PageUp => self.scroll_to(self.top.saturating_sub(self.page_height())),
+            Key::Char('g') | Key::Home => self.scroll_to(0),
+            Key::Char('G') | Key::End => self.scroll_to(usize::MAX),
+            Key::Char('/') => self.mode = Mode::Search(String::new()),
+            Key::Char('n') => match self.current_match {
+                Some(i) => self.find(i + 1, true),
+                None => self.find(self.top, true),
+            },
+            Key::Char('N') => match self.current_match {
+                Some(0) => {}
+                Some(i) => self.find(i - 1, false),
+                None => self.find(self.top, false),
+            },
+            Key::Char('s') => {
+                self.show_stderr = !self.show_stderr;
+                // Its index is off once stderr comes or goes
+                self.current_match = None;
+                self.scroll_to(self.top);
+            }
+            _ => {}
+        }
+
+        true
+    }
+
+    pub fn render<T: TermCursor + Write>(&self, stdout: &mut T) -> Result<(), Error> {
+        let (cols, rows) = self.size;
+        let lines = self.visible_lines();
+
+        stdout.write_term(format_args!("{}{}", clear::All, cursor::Hide))?;
+
+        for (row, line) in lines
+            .iter()
+            .skip(self.top)
+            .take(self.page_height())
+            .enumerate()
+        {
+            let text: String = line.text.chars().take(cols.into()).collect();
+
+            stdout.write_term(format_args!("{}", cursor::Goto(1, row as u16 + 1)))?;
+
+            if line.stream == Stream::Stderr {
+                stdout.write_term(format_args!("{}", color::Fg(color::Red)))?;
+            }
+
+            self.write_highlighted(stdout, &text)?;
+            stdout.write_term(format_args!("{}", color::Fg(color::Reset)))?;
+        }
+
+        let status = match &self.mode {
+            Mode::Search(query) => format!("/{}", query),
+            Mode::Scroll => {
+                let last = (self.top + self.page_height()).min(lines.len());
+                let stderr = if self.show_stderr {
+                    ""
+                } else {
+                    " (stderr hidden)"
+                };
+
+                format!(
+                    "{}-{}/{}{}  j/k scroll  / search  s stderr  q quit",
+                    (self.top + 1).min(last),
+                    last,
+                    lines.len(),
+                    stderr
+                )
+            }
+        };
+        let status: String = status.chars().take(cols.into()).collect();
+
+        stdout.write_term(format_args!(
+            "{}{}{}{}",
+            cursor::Goto(1, rows),
+            style::Invert,
+            status,
+            style::Reset
+        ))?;
+        stdout.flush()?;
+
+        Ok(())
+    }
+
+    // This shows the pager until `q` is pressed, with `size` asked for the
+    // terminal's size before each redraw
+    pub fn run<T: TermCursor + Write>(
+        &mut self,
+        stdout: &mut T,
+        keys: impl Iterator<Item = Result<Key, io::Error>>,
+        size: impl Fn() -> (u16, u16),
+    ) -> Result<(), Error> {
+        self.resize(size());
+        self.render(stdout)?;
+
+        for key in keys {
+            if !self.key(key?) {
+                break;
+            }
+
+            self.resize(size());
+            self.render(stdout)?;
+        }
+
+        Ok(())
+    }
+
+    // One row is taken by the status line
+    fn page_height(&self) -> usize {
+        usize::from(self.size.1.saturating_sub(1)).max(1)
+    }
+
+    fn max_top(&self) -> usize {
+        self.visible_lines()
+            .len()
+            .saturating_sub(self.page_height())
+    }
+
+    fn scroll_to(&mut self, top: usize) {
+        self.top = top.min(self.max_top());
+    }
+
+    // Finds the nearest line matching the query, starting at `from`, and
+    // scrolls only as far as it takes to show it
+    fn find(&mut self, from: usize, forward: bool) {
+        let Some(query) = &self.query else {
+            return;
+        };
+        let lines = self.visible_lines();
+        let is_match = |i: &usize| lines[*i].text.contains(query.as_str());
+        let found = if forward {
+            (from..lines.len()).find(is_match)
+        } else {
+            (0..from.saturating_add(1).min(lines.len()))
+                .rev()
+                .find(is_match)
+        };
+
+        let Some(i) = found else {
+            return;
+        };
+
+        self.current_match = Some(i);
+
+        if i < self.top {
+            self.scroll_to(i);
+        } else if i >= self.top + self.page_height() {
+            self.scroll_to(i + 1 - self.page_height());
+        }
+    }
+
+    fn write_highlighted<T: TermCursor + Write>(
+        &self,
+        stdout: &mut T,
+        text: &str,
+    ) -> Result<(), Error> {
+        let Some(query) = self.query.as_deref().filter(|query| !query.is_empty()) else {
+            stdout.write_term(format_args!("{}", text))?;
+            return Ok(());
+        };
+        let mut parts = text.split(query);
+
+        if let Some(first) = parts.next() {
+            stdout.write_term(format_args!("{}", first))?;
+        }
+
+        for part in parts {
+            stdout.write_term(format_args!(
+                "{}{}{}{}",
+                style::Invert,
+                query,
+                style::NoInvert,
+                part
+            ))?;
+        }
+
+        Ok(())
+    }
+}
+
+// This splits the output into lines per stream, in the order they were
+// finished, and cleans them up for display
+pub(crate) fn lines(output: &Output) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut pending: [Vec<u8>; 2] = Default::default();
+
+    for chunk in &output.combined {
+        let buf = &mut pending[chunk.stream as usize];
+
+        for &byte in &chunk.bytes {
+            if byte == b'\n' {
+                lines.push(line(chunk.stream, buf));
+                buf.clear();
+            } else {
+                buf.push(byte);
+            }
+        }
+    }
+
+    for (stream, buf) in [Stream::Stdout, Stream::Stderr].into_iter().zip(&pending) {
+        if !buf.is_empty() {
+            lines.push(line(stream, buf));
+        }
+    }
+
+    lines
+}
+
+// Colors and other escape sequences are dropped, and so is anything a `\r`
+// went back over (e.g. earlier states of a progress bar)
+fn line(stream: Stream, bytes: &[u8]) -> Line {
+    static ESCAPES: OnceLock<Regex> = OnceLock::new();
+
+    let escapes =
+        ESCAPES.get_or_init(|| Regex::new(r"\x1b(\[[0-?]*[ -/]*[@-~]|[@-Z\\-_])").unwrap());
+    let text = String::from_utf8_lossy(bytes);
+    let text = escapes.replace_all(text.trim_end_matches('\r'), "");
+    let text = text.rsplit('\r').next().unwrap_or_default();
+    let mut expanded = String::new();
+
+    for c in text.chars() {
+        if c == '\t' {
+            let spaces = TAB_WIDTH - expanded.chars().count() % TAB_WIDTH;
+
+            expanded.extend(std::iter::repeat_n(' ', spaces));
+        } else if !c.is_control() {
+            expanded.push(c);
+        }
+    }
+
+    Line {
+        stream,
+        text: expanded,
+    }
+}
@@ -0,0 +1,119 @@
+use std::{
+    fs::File,
+    io,
+    mem::MaybeUninit,
+    os::fd::{AsRawFd, FromRawFd, RawFd},
+    ptr,
+};
+
+pub type WindowSize = libc::winsize;
+
+// Used when we aren't attached to a terminal ourselves
+const DEFAULT_WINDOW_SIZE: WindowSize = WindowSize {
+    ws_row: 24,
+    ws_col: 80,
+    ws_xpixel: 0,
+    ws_ypixel: 0,
+};
+
+pub struct Pty {
+    pub master: File,
+    pub slave: File,
+}
+
+impl Pty {
+    pub fn open(size: WindowSize) -> io::Result<Self> {
+        let mut master: RawFd = -1;
+        let mut slave: RawFd = -1;
+
+        let result =
+            unsafe { libc::openpty(&mut master, &mut slave, ptr::null_mut(), ptr::null(), &size) };
+
+        if result == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // Only the child should inherit the slave, and only through its stdio
+        for fd in [master, slave] {
+            unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+        }
+
+        let pty = Self {
+            master: unsafe { File::from_raw_fd(master) },
+            slave: unsafe { File::from_raw_fd(slave) },
+        };
+
+        // We want the bytes exactly as the command wrote them, so `\n`
+        // shouldn't be turned into `\r\n` on the way
+        let mut termios = MaybeUninit::<libc::termios>::uninit();
+
+        unsafe {
+            if libc::tcgetattr(slave, termios.as_mut_ptr()) == 0 {
+                let mut termios = termios.assume_init();
+
+                termios.c_oflag &= !libc::ONLCR;
+                libc::tcsetattr(slave, libc::TCSANOW, &termios);
+            }
+        }
+
+        Ok(pty)
+    }
+
+    pub fn set_window_size(&self, size: WindowSize) -> io::Result<()> {
+        set_window_size(self.master.as_raw_fd(), size)
+    }
+}
+
+pub fn window_size() -> WindowSize {
+    [libc::STDOUT_FILENO, libc::STDIN_FILENO, libc::STDERR_FILENO]
+        .into_iter()
+        .find_map(|fd| {
+            let mut size = DEFAULT_WINDOW_SIZE;
+            let result = unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) };
+
+            (result == 0 && size.ws_col > 0).then_some(size)
+        })
+        .unwrap_or(DEFAULT_WINDOW_SIZE)
+}
+
+pub fn set_window_size(fd: RawFd, size: WindowSize) -> io::Result<()> {
+    if unsafe { libc::ioctl(fd, libc::TIOCSWINSZ, &size) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+// Whether a bare `\n` written to `fd` would leave the cursor where it is,
+// because it's a terminal in raw mode
+pub fn is_raw(fd: RawFd) -> bool {
+    let mut termios = MaybeUninit::<libc::termios>::uninit();
+
+    if unsafe { libc::tcgetattr(fd, termios.as_mut_ptr()) } != 0 {
+        return false;
+    }
+
+    unsafe { termios.assume_init() }.c_oflag & libc::OPOST == 0
+}
+
+// This function runs in the child between `fork` and `exec`, so it must only
+// make async-signal-safe calls
+pub fn make_controlling_terminal(fd: RawFd) -> io::Result<()> {
+    unsafe {
+        if libc::setsid() == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        if libc::ioctl(fd, libc::TIOCSCTTY as _, 0) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+// Reading the master side fails with `EIO` once every slave fd is closed,
+// which is how a terminal says "end of file"
+pub fn is_eof(error: &io::Error) -> bool {
+    error.raw_os_error() == Some(libc::EIO)
+}
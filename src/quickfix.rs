@@ -0,0 +1,205 @@
+use std::io::{self, Write};
+use std::sync::OnceLock;
+
+use regex::Regex;
+use termion::event::Key;
+use termion::{clear, cursor, style};
+
+use crate::error::Error;
+use crate::output::Output;
+use crate::pager;
+use crate::term_writer::TermCursor;
+
+// A place in a file that a compiler or linter complained about
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub path: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+struct Patterns {
+    // `error[E0308]: mismatched types`, followed by
+    // `  --> src/main.rs:10:5`
+    rustc_message: Regex,
+    rustc_location: Regex,
+    // `main.c:10:5: error: expected ';'` (clang and rustc's short format
+    // look the same)
+    gcc: Regex,
+    // `src/app.ts(10,5): error TS2322: ...` or, with `--pretty`,
+    // `src/app.ts:10:5 - error TS2322: ...`
+    tsc: Regex,
+    tsc_pretty: Regex,
+    // eslint's default format prints the file on its own line, followed by
+    // `  10:5  error  'x' is not defined  no-undef`
+    eslint_file: Regex,
+    eslint_problem: Regex,
+}
+
+fn patterns() -> &'static Patterns {
+    static PATTERNS: OnceLock<Patterns> = OnceLock::new();
+
+    PATTERNS.get_or_init(|| Patterns {
+        rustc_message: Regex::new(r"^((?:error|warning)(?:\[\w+\])?: .+)$").unwrap(),
+        rustc_location: Regex::new(r"^\s*--> (.+?):(\d+):(\d+)$").unwrap(),
+        gcc: Regex::new(r"^([^\s:][^:]*):(\d+):(\d+): ((?:fatal )?(?:error|warning|note)\b.*)$")
+            .unwrap(),
+        tsc: Regex::new(r"^([^\s(][^(]*)\((\d+),(\d+)\): ((?:error|warning) .+)$").unwrap(),
+        tsc_pretty: Regex::new(r"^(\S+):(\d+):(\d+) - ((?:error|warning) .+)$").unwrap(),
+        eslint_file: Regex::new(r"^(\S+\.[A-Za-z]\w*)$").unwrap(),
+        eslint_problem: Regex::new(r"^\s+(\d+):(\d+)\s+((?:error|warning)\s+.+)$").unwrap(),
+    })
+}
+
+// This finds the locations in a command's output, in order and without
+// duplicates
+pub fn locations(output: &Output) -> Vec<Location> {
+    let patterns = patterns();
+    let mut locations: Vec<Location> = Vec::new();
+    let mut rustc_message: Option<String> = None;
+    let mut eslint_file: Option<String> = None;
+
+    for line in pager::lines(output) {
+        let text = line.text.as_str();
+        let location = |path: &str, line: &str, column: &str, message: &str| Location {
+            path: path.to_owned(),
+            line: line.parse().unwrap_or(1),
+            column: column.parse().unwrap_or(1),
+            message: message.trim().to_owned(),
+        };
+        let found = if let Some(caps) = patterns.rustc_location.captures(text) {
+            let message = rustc_message.take().unwrap_or_default();
+
+            Some(location(&caps[1], &caps[2], &caps[3], &message))
+        } else if let Some(caps) = patterns.gcc.captures(text) {
+            Some(location(&caps[1], &caps[2], &caps[3], &caps[4]))
+        } else if let Some(caps) = patterns.tsc.captures(text) {
+            Some(location(&caps[1], &caps[2], &caps[3], &caps[4]))
+        } else if let Some(caps) = patterns.tsc_pretty.captures(text) {
+            Some(location(&caps[1], &caps[2], &caps[3], &caps[4]))
+        } else if let Some(caps) = patterns.eslint_problem.captures(text) {
+            eslint_file
+                .as_deref()
+                .map(|path| location(path, &caps[1], &caps[2], &caps[3]))
+        } else {
+            if let Some(caps) = patterns.rustc_message.captures(text) {
+                rustc_message = Some(caps[1].to_owned());
+            } else if let Some(caps) = patterns.eslint_file.captures(text) {
+                eslint_file = Some(caps[1].to_owned());
+            }
+
+            None
+        };
+
+        if let Some(found) = found {
+            let is_duplicate = locations.iter().any(|location| {
+                (&location.path, location.line, location.column)
+                    == (&found.path, found.line, found.column)
+            });
+
+            if !is_duplicate {
+                locations.push(found);
+            }
+        }
+    }
+
+    locations
+}
+
+// A list of locations to choose one from, drawn over the whole terminal
+pub struct Picker<'a> {
+    locations: &'a [Location],
+    selected: usize,
+    size: (u16, u16),
+}
+
+impl<'a> Picker<'a> {
+    pub fn new(locations: &'a [Location], size: (u16, u16)) -> Self {
+        Self {
+            locations,
+            selected: 0,
+            size,
+        }
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    // This returns `Some` once a location was picked (`Some(None)` when the
+    // picker was closed without one)
+    pub fn key(&mut self, key: Key) -> Option<Option<&'a Location>> {
+        match key {
+            Key::Char('\n') => return Some(self.locations.get(self.selected)),
+            Key::Char('q') | Key::Esc => return Some(None),
+            Key::Char('j') | Key::Down => {
+                self.selected = (self.selected + 1).min(self.locations.len().saturating_sub(1));
+            }
+            Key::Char('k') | Key::Up => self.selected = self.selected.saturating_sub(1),
+            _ => {}
+        }
+
+        None
+    }
+
+    pub fn render<T: TermCursor + Write>(&self, stdout: &mut T) -> Result<(), Error> {
+        let (cols, rows) = self.size;
+        // One row is taken by the title
+        let height = usize::from(rows.saturating_sub(1)).max(1);
+        let top = self.selected.saturating_sub(height - 1);
+
+        stdout.write_term(format_args!(
+            "{}{}{}Jump to (j/k, Enter, q){}",
+            clear::All,
+            cursor::Hide,
+            cursor::Goto(1, 1),
+            style::Reset
+        ))?;
+
+        for (row, (i, location)) in self
+            .locations
+            .iter()
+            .enumerate()
+            .skip(top)
+            .take(height)
+            .enumerate()
+        {
+            let text = format!(
+                "{}:{}:{}  {}",
+                location.path, location.line, location.column, location.message
+            );
+            let text: String = text.chars().take(cols.into()).collect();
+
+            stdout.write_term(format_args!("{}", cursor::Goto(1, row as u16 + 2)))?;
+
+            if i == self.selected {
+                stdout.write_term(format_args!("{}{}{}", style::Invert, text, style::Reset))?;
+            } else {
+                stdout.write_term(format_args!("{}", text))?;
+            }
+        }
+
+        stdout.flush()?;
+
+        Ok(())
+    }
+
+    pub fn run<T: TermCursor + Write>(
+        &mut self,
+        stdout: &mut T,
+        keys: impl Iterator<Item = Result<Key, io::Error>>,
+    ) -> Result<Option<&'a Location>, Error> {
+        self.render(stdout)?;
+
+        for key in keys {
+            if let Some(picked) = self.key(key?) {
+                return Ok(picked);
+            }
+
+            self.render(stdout)?;
+        }
+
+        Ok(None)
+    }
+}
@@ -1,4 +1,6 @@
+use crate::error::Error;
 use crate::term_writer::TermCursor;
+use crate::terminal;
 use std::io::Write;
 
 pub struct Screen<T: TermCursor + Write> {
@@ -11,39 +13,58 @@ impl<T: TermCursor + Write> Screen<T> {
     }
 
     //To-do: maybe functions like these should belong to `TermWriter`?
-    pub fn add_newline(&mut self) {
-        self.stdout.write_term(format_args!("\r\n")).unwrap();
+    pub fn add_newline(&mut self) -> Result<(), Error> {
+        self.stdout.write_term(format_args!("\r\n"))?;
+
+        Ok(())
     }
 
-    pub fn show_cursor(&mut self) {
+    pub fn show_cursor(&mut self) -> Result<(), Error> {
         self.stdout
-            .write_term(format_args!("{}", termion::cursor::Show))
-            .unwrap();
-        self.stdout.flush().unwrap();
+            .write_term(format_args!("{}", termion::cursor::Show))?;
+        self.stdout.flush()?;
+
+        Ok(())
     }
 
-    pub fn show_prompt(&mut self, message: &str) {
-        self.stdout
-            .write_term(format_args!("{}\r\n", message))
-            .unwrap();
+    pub fn show_prompt(&mut self, message: &str) -> Result<(), Error> {
+        self.stdout.write_term(format_args!("{}\r\n", message))?;
+
+        Ok(())
     }
 
-    pub fn show_menu(&mut self, items: &[String]) {
+    pub fn show_menu(&mut self, items: &[String]) -> Result<(), Error> {
         for item in items {
-            self.stdout
-                .write_term(format_args!("{}\r\n", item))
-                .unwrap();
+            self.stdout.write_term(format_args!("{}\r\n", item))?;
         }
+
+        Ok(())
     }
 
-    pub fn clear_all(&mut self) {
+    pub fn clear_all(&mut self) -> Result<(), Error> {
+        self.stdout.write_term(format_args!(
+            "{}{}{}",
+            termion::clear::All,
+            termion::cursor::Goto(1, 1),
+            termion::cursor::Hide,
+        ))?;
+
+        Ok(())
+    }
+
+    pub fn enter_alternate_screen(&mut self) -> Result<(), Error> {
+        self.stdout
+            .write_term(format_args!("{}", termion::screen::ToAlternateScreen))?;
+        terminal::set_alternate_screen(true);
+
+        Ok(())
+    }
+
+    pub fn leave_alternate_screen(&mut self) -> Result<(), Error> {
         self.stdout
-            .write_term(format_args!(
-                "{}{}{}",
-                termion::clear::All,
-                termion::cursor::Goto(1, 1),
-                termion::cursor::Hide,
-            ))
-            .unwrap();
+            .write_term(format_args!("{}", termion::screen::ToMainScreen))?;
+        terminal::set_alternate_screen(false);
+
+        Ok(())
     }
 }
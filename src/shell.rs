@@ -0,0 +1,101 @@
+use std::{env, fmt, process::Command, str::FromStr};
+
+use crate::error::Error;
+use crate::utils::split_words;
+
+// What runs a command: a shell invocation the command string is appended to
+// (e.g. `bash -lc`), or nothing at all, in which case the command's own
+// words are executed directly
+#[derive(Debug, Clone, PartialEq)]
+pub enum Shell {
+    Command(Vec<String>),
+    None,
+}
+
+impl Default for Shell {
+    // Same shell `script -c` used to pick
+    fn default() -> Self {
+        let shell = env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_owned());
+
+        Shell::Command(vec![shell, "-c".to_owned()])
+    }
+}
+
+impl FromStr for Shell {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words = split_words(s);
+
+        match words.as_slice() {
+            [] => Err(Error::Config("the shell can't be empty".to_owned())),
+            [word] if word == "none" => Ok(Shell::None),
+            _ => Ok(Shell::Command(words)),
+        }
+    }
+}
+
+impl fmt::Display for Shell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Shell::Command(words) => write!(f, "{}", words.join(" ")),
+            Shell::None => write!(f, "none"),
+        }
+    }
+}
+
+impl Shell {
+    pub fn argv(&self, cmd_str: &str) -> Vec<String> {
+        match self {
+            Shell::Command(words) => {
+                let mut argv = words.clone();
+
+                argv.push(cmd_str.to_owned());
+                argv
+            }
+            Shell::None => split_words(cmd_str),
+        }
+    }
+
+    pub fn command(&self, cmd_str: &str) -> Command {
+        let mut argv = self.argv(cmd_str).into_iter();
+        let mut cmd = Command::new(argv.next().unwrap_or_default());
+
+        cmd.args(argv);
+        cmd
+    }
+}
+
+// The language of the shell a printed command is pasted into (see
+// `Keymap::shell_cmd`), which only matters for how the input is passed
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Syntax {
+    // bash, zsh and sh
+    #[default]
+    Posix,
+    Fish,
+}
+
+impl FromStr for Syntax {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "posix" | "sh" | "bash" | "zsh" => Ok(Syntax::Posix),
+            "fish" => Ok(Syntax::Fish),
+            _ => Err(Error::Usage(format!(
+                "`{}` isn't one of `posix`, `bash`, `zsh` or `fish`",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Syntax {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Syntax::Posix => write!(f, "posix"),
+            Syntax::Fish => write!(f, "fish"),
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use crate::error::Error;
+
+// Each widget binds Ctrl-G to open the palette on the terminal and insert
+// the chosen command at the cursor. The command comes back on fd 3, since
+// stdout is where the palette is drawn.
+
+const BASH: &str = r#"__quicommand_widget() {
+  local cmd
+  cmd="$(quicommand --print-fd 3 3>&1 1>/dev/tty)" || return
+  READLINE_LINE="${READLINE_LINE:0:$READLINE_POINT}$cmd${READLINE_LINE:$READLINE_POINT}"
+  READLINE_POINT=$((READLINE_POINT + ${#cmd}))
+}
+bind -x '"\C-g": __quicommand_widget'
+"#;
+
+const ZSH: &str = r#"__quicommand_widget() {
+  local cmd
+  cmd="$(quicommand --print-fd 3 3>&1 1>/dev/tty)" && LBUFFER+="$cmd"
+  zle reset-prompt
+}
+zle -N __quicommand_widget
+bindkey '^G' __quicommand_widget
+"#;
+
+const FISH: &str = r#"function __quicommand_widget
+    set -l cmd (quicommand --print-fd 3 --print-shell fish 3>&1 1>/dev/tty | string collect)
+    and commandline --insert -- $cmd
+    commandline --function repaint
+end
+bind \cg __quicommand_widget
+"#;
+
+// What `eval "$(quicommand shell-init bash)"` (or `zsh`, or
+// `quicommand shell-init fish | source`) runs
+pub fn script(shell: &str) -> Result<&'static str, Error> {
+    match shell {
+        "bash" => Ok(BASH),
+        "zsh" => Ok(ZSH),
+        "fish" => Ok(FISH),
+        _ => Err(Error::Usage(format!(
+            "`{}` isn't one of `bash`, `zsh` or `fish`",
+            shell
+        ))),
+    }
+}
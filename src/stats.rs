@@ -0,0 +1,194 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::audit::Record;
+use crate::keymap::Keymap;
+
+// How many of a keymap's most used prompt values are shown
+const TOP_INPUTS: usize = 3;
+
+// How one keymap was used, going by the audit log
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeymapStats {
+    pub key: char,
+    pub description: String,
+    pub runs: usize,
+    pub successes: usize,
+    pub median: Duration,
+    pub p95: Duration,
+    // The values typed at the prompt most often, with how often
+    pub top_inputs: Vec<(String, usize)>,
+}
+
+impl KeymapStats {
+    // Between 0 and 1
+    pub fn success_rate(&self) -> f64 {
+        self.successes as f64 / self.runs as f64
+    }
+}
+
+// A keymap from the config files that was never run
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unused {
+    pub key: char,
+    pub description: String,
+    pub source: Option<PathBuf>,
+}
+
+// What `quicommand stats` prints
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Stats {
+    // Most runs first
+    pub keymaps: Vec<KeymapStats>,
+    pub unused: Vec<Unused>,
+}
+
+impl Stats {
+    // Records are matched to keymaps by key and description, like
+    // `audit::Frecency` does. Keymaps that are gone still get stats.
+    pub fn new(records: &[Record], keymaps: &[Keymap]) -> Self {
+        // In the order they were first run, so ties below keep it
+        let mut groups: Vec<(char, &str, Vec<&Record>)> = Vec::new();
+        let mut indices: HashMap<(char, &str), usize> = HashMap::new();
+
+        for record in records {
+            let i = *indices
+                .entry((record.key, &record.description))
+                .or_insert_with(|| {
+                    groups.push((record.key, &record.description, Vec::new()));
+                    groups.len() - 1
+                });
+
+            groups[i].2.push(record);
+        }
+
+        let mut stats: Vec<KeymapStats> = groups
+            .into_iter()
+            .map(|(key, description, records)| keymap_stats(key, description, &records))
+            .collect();
+
+        stats.sort_by_key(|stats| Reverse(stats.runs));
+
+        let unused = keymaps
+            .iter()
+            .filter(|keymap| !indices.contains_key(&(keymap.key, keymap.description.as_str())))
+            .map(|keymap| Unused {
+                key: keymap.key,
+                description: keymap.description.clone(),
+                source: keymap.source.clone(),
+            })
+            .collect();
+
+        Self {
+            keymaps: stats,
+            unused,
+        }
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.keymaps.is_empty() {
+            writeln!(f, "Nothing was run yet")?;
+        } else {
+            // The same widths as the rows below
+            writeln!(
+                f,
+                "{:<3}  {:>5}  {:>7}  {:>6}  {:>6}  description",
+                "key", "runs", "success", "median", "p95"
+            )?;
+        }
+
+        for stats in &self.keymaps {
+            writeln!(
+                f,
+                "{:<3}  {:>5}  {:>6.0}%  {:>5.1}s  {:>5.1}s  {}",
+                stats.key,
+                stats.runs,
+                stats.success_rate() * 100.0,
+                stats.median.as_secs_f64(),
+                stats.p95.as_secs_f64(),
+                stats.description
+            )?;
+
+            if !stats.top_inputs.is_empty() {
+                let inputs: Vec<String> = stats
+                    .top_inputs
+                    .iter()
+                    .map(|(input, count)| format!("{:?} ({})", input, count))
+                    .collect();
+
+                writeln!(f, "     inputs: {}", inputs.join(", "))?;
+            }
+        }
+
+        if !self.unused.is_empty() {
+            writeln!(f, "\nNever used:")?;
+        }
+
+        for unused in &self.unused {
+            match &unused.source {
+                Some(path) => writeln!(
+                    f,
+                    "{}  {}  ({})",
+                    unused.key,
+                    unused.description,
+                    path.display()
+                )?,
+                None => writeln!(f, "{}  {}  (built in)", unused.key, unused.description)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn keymap_stats(key: char, description: &str, records: &[&Record]) -> KeymapStats {
+    let mut durations: Vec<Duration> = records
+        .iter()
+        .map(|record| (record.end - record.start).to_std().unwrap_or_default())
+        .collect();
+    let mut inputs: Vec<(String, usize)> = Vec::new();
+    let mut indices: HashMap<&str, usize> = HashMap::new();
+
+    durations.sort();
+
+    for input in records.iter().filter_map(|record| record.input.as_deref()) {
+        let i = *indices.entry(input).or_insert_with(|| {
+            inputs.push((input.to_owned(), 0));
+            inputs.len() - 1
+        });
+
+        inputs[i].1 += 1;
+    }
+
+    // Ties keep the value that was typed first
+    inputs.sort_by_key(|&(_, count)| Reverse(count));
+    inputs.truncate(TOP_INPUTS);
+
+    KeymapStats {
+        key,
+        description: description.to_owned(),
+        runs: records.len(),
+        successes: records
+            .iter()
+            .filter(|record| record.exit_code == 0)
+            .count(),
+        median: percentile(&durations, 50),
+        p95: percentile(&durations, 95),
+        top_inputs: inputs,
+    }
+}
+
+// The nearest-rank percentile of `sorted`, so always one of its values
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+    let rank = (sorted.len() * percent).div_ceil(100);
+
+    sorted
+        .get(rank.saturating_sub(1))
+        .copied()
+        .unwrap_or_default()
+}
@@ -1,52 +1,324 @@
+use crate::audit::{self, Filter, Record};
 use crate::cmd_runner::{CmdRunner, CmdType};
+use crate::config::Config;
+use crate::error::Error;
 use crate::input;
-use crate::input::{Input, InputError};
-use crate::keymap::Keymap;
+use crate::input::Input;
+use crate::keymap::{Keymap, PassInput, AD_HOC_KEY};
+use crate::output::Output;
+use crate::quickfix::Location;
 use crate::screen::Screen;
+use crate::shell::Syntax;
+use crate::supervisor::Termination;
 use crate::term_writer::TermCursor;
-use crate::utils::escape_backticks;
-use std::io::Write;
+use chrono::Local;
+use std::io::{self, Write};
+use std::os::unix::process::ExitStatusExt;
+use std::time::{Duration, Instant};
+use termion::color;
 use termion::event::Key;
 
 #[derive(Debug, PartialEq)]
 pub enum Process {
-    Output(std::process::Output),
+    Output(Output),
+    // Stopped by a signal that was forwarded to it (SIGINT, SIGTERM, ...),
+    // with whatever it printed until then
+    Cancelled(Output, i32),
+    TimedOut(Output),
     Exit,
 }
 
+impl Process {
+    fn new(output: Output, termination: Termination) -> Self {
+        match termination {
+            Termination::Exited => Process::Output(output),
+            Termination::Cancelled(signal) => Process::Cancelled(output, signal),
+            Termination::TimedOut => Process::TimedOut(output),
+        }
+    }
+
+    // What quicommand itself exits with, following the shell's conventions
+    // for signals (128 + n) and `timeout(1)` for timeouts
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Process::Output(output) => {
+                let status = output.status;
+
+                status
+                    .code()
+                    .or_else(|| status.signal().map(|signal| 128 + signal))
+                    .unwrap_or(1)
+            }
+            Process::Cancelled(_, signal) => 128 + signal,
+            Process::TimedOut(_) => 124,
+            Process::Exit => 0,
+        }
+    }
+
+    // What the command wrote, however it ended
+    pub fn output(&self) -> Option<&Output> {
+        match self {
+            Process::Output(output) | Process::Cancelled(output, _) | Process::TimedOut(output) => {
+                Some(output)
+            }
+            Process::Exit => None,
+        }
+    }
+
+    pub fn success(&self) -> bool {
+        self.exit_code() == 0
+    }
+
+    // E.g. "✔ exit 0 in 3.2s" or "✘ exit 101 in 0.4s"
+    pub fn status_line(&self, elapsed: Duration) -> Option<String> {
+        let mark = if self.success() { '✔' } else { '✘' };
+        let secs = elapsed.as_secs_f64();
+        let status = match self {
+            Process::Output(output) => match output.status.signal() {
+                Some(signal) => format!("killed by signal {}", signal),
+                None => format!("exit {}", self.exit_code()),
+            },
+            Process::Cancelled(..) => "cancelled".to_owned(),
+            Process::TimedOut(_) => "timed out".to_owned(),
+            Process::Exit => return None,
+        };
+
+        Some(format!("{} {} in {:.1}s", mark, status, secs))
+    }
+}
+
+fn cmd_runner(config: &Config, keymap: &Keymap, cmd_str: &str) -> CmdRunner {
+    let cmd_type = config.cmd_type(keymap, cmd_str);
+    let shell = config.shell(keymap).clone();
+    let cmd_runner = CmdRunner::with_cmd_type(cmd_str, cmd_type)
+        .with_shell(shell)
+        .with_memory_cap(config.output_memory_cap)
+        .with_retention(keymap.output.unwrap_or_default())
+        .with_output_mode(config.output_mode);
+
+    match keymap.timeout {
+        Some(timeout) => cmd_runner.with_timeout(timeout),
+        None => cmd_runner,
+    }
+}
+
+fn run(config: &Config, cmd_runner: &mut CmdRunner) -> Result<Process, Error> {
+    let start = Instant::now();
+    let output = match cmd_runner.cmd_type {
+        CmdType::Interactive => cmd_runner.run()?,
+        CmdType::Script => cmd_runner.run_with_output()?,
+    };
+    let process = Process::new(output, cmd_runner.termination);
+
+    if config.show_status {
+        show_status(&process, start.elapsed())?;
+    }
+
+    Ok(process)
+}
+
+// Editors always get the terminal, whatever they're called
+pub fn edit(config: &Config, location: &Location) -> Result<Process, Error> {
+    let cmd_str = config.editor_cmd(location);
+    let mut cmd_runner =
+        CmdRunner::with_cmd_type(&cmd_str, CmdType::Interactive).with_shell(config.shell.clone());
+
+    run(config, &mut cmd_runner)
+}
+
+// The input goes wherever `pass_input` says, besides the placeholders
+fn with_input(cmd_runner: CmdRunner, keymap: &Keymap, input: Option<&str>) -> CmdRunner {
+    match input {
+        Some(input) => match keymap.pass_input {
+            PassInput::Cmd => cmd_runner,
+            PassInput::Env => cmd_runner.with_env(keymap.prompt_env_var(), input),
+            PassInput::Stdin => cmd_runner.with_stdin(format!("{}\n", input)),
+        },
+        None => cmd_runner,
+    }
+}
+
+// This runs a keymap's command, with the prompt's input if it has one, for
+// the palette and `quicommand run` alike
+pub fn run_keymap(config: &Config, keymap: &Keymap, input: Option<&str>) -> Result<Process, Error> {
+    let (cmd_str, logged_cmd) = keymap.render_cmds(input, &config.context);
+    let mut cmd_runner = with_input(cmd_runner(config, keymap, &cmd_str), keymap, input);
+
+    run_audited(config, keymap, input, &logged_cmd, &mut cmd_runner)
+}
+
+// This runs a command that started as the keymap's but was edited, with
+// the keymap's settings and the prompt's `input` otherwise
+pub fn run_edited(
+    config: &Config,
+    keymap: &Keymap,
+    cmd_str: &str,
+    input: Option<&str>,
+) -> Result<Process, Error> {
+    // The placeholders are filled in by now, so their values are looked for
+    // wherever they ended up
+    let mut secrets = keymap.env_values(&config.context);
+
+    if keymap.secret {
+        secrets.extend(input.map(str::to_owned));
+    }
+
+    let logged_cmd = secrets.iter().fold(cmd_str.to_owned(), |cmd, secret| {
+        audit::redact(&cmd, secret)
+    });
+    let mut cmd_runner = with_input(cmd_runner(config, keymap, cmd_str), keymap, input);
+
+    run_audited(config, keymap, input, &logged_cmd, &mut cmd_runner)
+}
+
+// `logged_cmd` is what the audit log gets instead of the command, with
+// secrets left out. Not being able to write the log doesn't change how the
+// command went, so this only warns about it.
+fn run_audited(
+    config: &Config,
+    keymap: &Keymap,
+    input: Option<&str>,
+    logged_cmd: &str,
+    cmd_runner: &mut CmdRunner,
+) -> Result<Process, Error> {
+    // Nothing is run, so there's nothing to record either
+    if config.dry_run {
+        show_dry_run(cmd_runner)?;
+
+        return Ok(Process::Exit);
+    }
+
+    let start = Local::now();
+    let process = run(config, cmd_runner)?;
+
+    if let Some(audit_log) = &config.audit_log {
+        let record = Record::new(keymap, logged_cmd, input, &process, start);
+
+        if let Err(e) = record.and_then(|record| audit_log.append(&record)) {
+            eprint!("quicommand: couldn't write the audit log: {}\r\n", e);
+        }
+    }
+
+    Ok(process)
+}
+
+// The commands typed on the palette's command line before, oldest first and
+// each only once
+pub fn ad_hoc_history(config: &Config) -> Vec<String> {
+    let filter = Filter {
+        key: Some(AD_HOC_KEY),
+        ..Filter::default()
+    };
+    let records = match &config.audit_log {
+        Some(audit_log) => audit_log.records(&filter).unwrap_or_default(),
+        None => Vec::new(),
+    };
+    let mut history: Vec<String> = Vec::new();
+
+    for record in records {
+        history.retain(|cmd| *cmd != record.cmd);
+        history.push(record.cmd);
+    }
+
+    history
+}
+
+// Like `show_status`, this writes to stdout once the screen is gone
+fn show_dry_run(cmd_runner: &CmdRunner) -> Result<(), Error> {
+    let mut stdout = io::stdout();
+
+    for line in cmd_runner.describe()?.lines() {
+        write!(stdout, "{}\r\n", line)?;
+    }
+
+    stdout.flush()?;
+
+    Ok(())
+}
+
+// The screen is gone by the time the command finishes, so this writes to
+// stdout like the command's own output
+fn show_status(process: &Process, elapsed: Duration) -> Result<(), Error> {
+    let Some(status_line) = process.status_line(elapsed) else {
+        return Ok(());
+    };
+    let mut stdout = io::stdout();
+
+    if let Some(output) = process.output() {
+        if !output.ends_with_newline() {
+            write!(stdout, "\r\n")?;
+        }
+    }
+
+    if termion::is_tty(&stdout) {
+        let fg = if process.success() {
+            color::Fg(color::Green).to_string()
+        } else {
+            color::Fg(color::Red).to_string()
+        };
+
+        write!(
+            stdout,
+            "{}{}{}\r\n",
+            fg,
+            status_line,
+            color::Fg(color::Reset)
+        )?;
+    } else {
+        write!(stdout, "{}\r\n", status_line)?;
+    }
+
+    stdout.flush()?;
+
+    Ok(())
+}
+
 pub struct Step<T: TermCursor + Write> {
     pub screen: Screen<T>,
+    pub config: Config,
 }
 
 impl<T: TermCursor + Write> Step<T> {
     pub fn new(screen: Screen<T>) -> Self {
-        Step { screen }
+        Step {
+            screen,
+            config: Config::default(),
+        }
     }
 
-    pub fn show_select_cmd(&mut self, keymaps: &[Keymap]) {
-        self.screen.clear_all();
-        self.screen.show_prompt("Please select a command:");
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn show_select_cmd(&mut self, keymaps: &[Keymap]) -> Result<(), Error> {
+        self.screen.clear_all()?;
+        self.screen.show_prompt("Please select a command:")?;
+
+        if self.config.dry_run {
+            self.screen
+                .show_prompt("Dry run: commands are shown, not run (Ctrl-D to toggle)")?;
+        }
 
         let menu_items: Vec<String> = keymaps
             .iter()
             .map(|keymap| format!("{}  {}", keymap.key, keymap.description))
             .collect();
 
-        self.screen.show_menu(&menu_items);
+        self.screen.show_menu(&menu_items)
     }
 
     pub fn input_from_prompt(
         &mut self,
         prompt: Option<&str>,
         stdin: impl Iterator<Item = Result<Key, std::io::Error>>,
-    ) -> Result<Input, InputError> {
+    ) -> Result<Input, Error> {
         match prompt {
-            Some(_) => {
-                self.screen.show_prompt(&prompt.unwrap());
-                self.screen.show_cursor();
+            Some(prompt) => {
+                self.screen.show_prompt(prompt)?;
+                self.screen.show_cursor()?;
 
                 let input = input::input_from_keys(stdin, &mut self.screen.stdout)?;
-                // To-do: move `escape_backtips` to here?
 
                 Ok(input)
             }
@@ -54,50 +326,129 @@ impl<T: TermCursor + Write> Step<T> {
         }
     }
 
+    // This loads the command the input resolves to into the line editor, so
+    // it can be changed before it's run (see `process_edited`). Input that
+    // goes to the environment or stdin still goes there, and isn't shown.
+    pub fn edit_cmd(
+        &mut self,
+        result: Result<Input, Error>,
+        keymap: &Keymap,
+        stdin: impl Iterator<Item = Result<Key, std::io::Error>>,
+    ) -> Result<Input, Error> {
+        let input = match result {
+            Ok(Input::Text(i)) => {
+                // Because the input doesn't start a newline
+                self.screen.add_newline()?;
+                Some(i)
+            }
+            Ok(Input::None) => None,
+            Ok(Input::Cancel) => return Ok(Input::Cancel),
+            Err(e) => return self.input_error(e),
+        };
+        let cmd = keymap.render_cmd(input.as_deref(), &self.config.context);
+
+        self.screen.show_prompt("Edit the command:")?;
+        self.screen.show_cursor()?;
+
+        Ok(input::edit_from_keys(cmd, stdin, &mut self.screen.stdout)?)
+    }
+
+    // The palette's command line, for commands that aren't bound to a key.
+    // What it returns is run with `process_edited`.
+    pub fn input_ad_hoc(
+        &mut self,
+        history: &[String],
+        stdin: impl Iterator<Item = Result<Key, std::io::Error>>,
+    ) -> Result<Input, Error> {
+        self.screen.show_prompt("Command:")?;
+        self.screen.show_cursor()?;
+
+        Ok(input::input_with_history(
+            history,
+            stdin,
+            &mut self.screen.stdout,
+        )?)
+    }
+
+    // Like `process_input`, but this returns the command for a shell that
+    // speaks `syntax` (see `Keymap::shell_cmd`) instead of running it, or
+    // `None` when cancelled
+    pub fn resolve_input(
+        &mut self,
+        result: Result<Input, Error>,
+        keymap: &Keymap,
+        syntax: Syntax,
+    ) -> Result<Option<String>, Error> {
+        let context = &self.config.context;
+
+        match result {
+            Ok(Input::Text(i)) => Ok(Some(keymap.shell_cmd(Some(&i), context, syntax))),
+            Ok(Input::None) => Ok(Some(keymap.shell_cmd(None, context, syntax))),
+            Ok(Input::Cancel) => Ok(None),
+            Err(e) => self.input_error(e),
+        }
+    }
+
     pub fn process_input(
         mut self,
-        result: Result<Input, InputError>,
+        result: Result<Input, Error>,
         keymap: &Keymap,
-    ) -> Result<Process, InputError> {
+    ) -> Result<Process, Error> {
         match result {
             Ok(Input::Text(i)) => {
                 // Because the input doesn't start a newline
-                self.screen.add_newline();
-                self.screen.show_cursor();
+                self.screen.add_newline()?;
+                self.screen.show_cursor()?;
                 drop(self.screen.stdout);
 
-                let input = escape_backticks(&i);
-                let keymap_cmd = keymap.cmd.replace("{}", &input);
-
-                let mut cmd_runner = CmdRunner::new(&keymap_cmd);
-                let output = cmd_runner.run_with_output().unwrap();
-
-                Ok(Process::Output(output))
+                run_keymap(&self.config, keymap, Some(&i))
             }
             Ok(Input::None) => {
-                self.screen.show_cursor();
+                self.screen.show_cursor()?;
                 drop(self.screen.stdout);
 
-                let mut cmd_runner = CmdRunner::new(&keymap.cmd);
-
-                let output = match cmd_runner.cmd_type {
-                    CmdType::Interactive => cmd_runner.run().unwrap(),
-                    CmdType::Script => cmd_runner.run_with_output().unwrap(),
-                };
-
-                Ok(Process::Output(output))
+                run_keymap(&self.config, keymap, None)
             }
             Ok(Input::Cancel) => {
-                self.screen.add_newline();
+                self.screen.add_newline()?;
                 Ok(Process::Exit)
             }
-            Err(e) => {
-                self.screen
-                    .stdout
-                    .write_term(format_args!("Invalid input: {}\r\n", e))
-                    .unwrap();
-                Err(e)
+            Err(e) => self.input_error(e),
+        }
+    }
+
+    // Runs what `edit_cmd` returned, with `input` being what was typed at
+    // the keymap's prompt before
+    pub fn process_edited(
+        mut self,
+        result: Result<Input, Error>,
+        keymap: &Keymap,
+        input: Option<&str>,
+    ) -> Result<Process, Error> {
+        match result {
+            Ok(Input::Text(cmd)) => {
+                self.screen.add_newline()?;
+                self.screen.show_cursor()?;
+                drop(self.screen.stdout);
+
+                run_edited(&self.config, keymap, &cmd, input)
+            }
+            Ok(Input::None) | Ok(Input::Cancel) => {
+                self.screen.add_newline()?;
+                Ok(Process::Exit)
             }
+            Err(e) => self.input_error(e),
         }
     }
+
+    // Shows why the input was rejected before passing the error on
+    fn input_error<U>(&mut self, e: Error) -> Result<U, Error> {
+        if let Error::Input(e) = &e {
+            self.screen
+                .stdout
+                .write_term(format_args!("Invalid input: {}\r\n", e))?;
+        }
+
+        Err(e)
+    }
 }
@@ -0,0 +1,211 @@
+use std::{
+    fmt, io,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering},
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use signal_hook::{
+    consts::{SIGHUP, SIGINT, SIGQUIT, SIGTERM},
+    iterator::{Handle, Signals},
+};
+
+use crate::error::Error;
+
+// How long a timed out command gets to exit after SIGTERM before SIGKILL
+const KILL_GRACE: Duration = Duration::from_secs(2);
+
+// How many commands are being supervised right now
+static SUPERVISING: AtomicUsize = AtomicUsize::new(0);
+
+pub fn is_supervising() -> bool {
+    SUPERVISING.load(Ordering::SeqCst) > 0
+}
+
+// Counts as supervising for as long as it's alive
+struct Active;
+
+impl Active {
+    fn new() -> Self {
+        SUPERVISING.fetch_add(1, Ordering::SeqCst);
+        Active
+    }
+}
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        SUPERVISING.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Written to the audit log as `exited`, `cancelled:N` or `timed_out`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum Termination {
+    Exited,
+    // By the signal that was forwarded to the command
+    Cancelled(i32),
+    TimedOut,
+}
+
+impl FromStr for Termination {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            let message = format!("`{}` isn't how a command ended", s);
+
+            Error::Io(io::Error::new(io::ErrorKind::InvalidData, message))
+        };
+
+        match s.split_once(':') {
+            None if s == "exited" => Ok(Termination::Exited),
+            None if s == "timed_out" => Ok(Termination::TimedOut),
+            // Logs written before the signal was recorded
+            None if s == "cancelled" => Ok(Termination::Cancelled(SIGINT)),
+            Some(("cancelled", signal)) => signal
+                .parse()
+                .map(Termination::Cancelled)
+                .map_err(|_| invalid()),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl TryFrom<String> for Termination {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Termination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Termination::Exited => write!(f, "exited"),
+            Termination::Cancelled(signal) => write!(f, "cancelled:{}", signal),
+            Termination::TimedOut => write!(f, "timed_out"),
+        }
+    }
+}
+
+impl From<Termination> for String {
+    fn from(termination: Termination) -> Self {
+        termination.to_string()
+    }
+}
+
+// This registers for terminating signals before a command is spawned, so
+// they can't take us down before it's being watched
+pub struct Supervisor {
+    active: Active,
+    signals: Signals,
+    forward_sigint: bool,
+    timeout: Option<Duration>,
+}
+
+impl Supervisor {
+    // `forward_sigint` is off for commands sharing our terminal, since the
+    // terminal already sends them Ctrl-C itself
+    pub fn new(forward_sigint: bool, timeout: Option<Duration>) -> io::Result<Self> {
+        let signals = Signals::new([SIGHUP, SIGINT, SIGQUIT, SIGTERM])?;
+
+        Ok(Self {
+            active: Active::new(),
+            signals,
+            forward_sigint,
+            timeout,
+        })
+    }
+
+    // `target` is what `kill` gets: a pid, or a negated process group id
+    pub fn watch(self, target: libc::pid_t) -> Watch {
+        // The last signal that was forwarded, 0 until there's one
+        let cancelled = Arc::new(AtomicI32::new(0));
+        let timed_out = Arc::new(AtomicBool::new(false));
+        let mut signals = self.signals;
+        let handle = signals.handle();
+        let forward_sigint = self.forward_sigint;
+        let signal_thread = {
+            let cancelled = Arc::clone(&cancelled);
+
+            thread::spawn(move || {
+                for signal in signals.forever() {
+                    if signal == SIGINT && !forward_sigint {
+                        continue;
+                    }
+
+                    cancelled.store(signal, Ordering::SeqCst);
+                    unsafe { libc::kill(target, signal) };
+                }
+            })
+        };
+        let (done, done_rx) = mpsc::channel();
+        let timer_thread = self.timeout.map(|timeout| {
+            let timed_out = Arc::clone(&timed_out);
+
+            thread::spawn(move || {
+                if done_rx.recv_timeout(timeout) != Err(RecvTimeoutError::Timeout) {
+                    return;
+                }
+
+                timed_out.store(true, Ordering::SeqCst);
+                unsafe { libc::kill(target, libc::SIGTERM) };
+
+                if done_rx.recv_timeout(KILL_GRACE) == Err(RecvTimeoutError::Timeout) {
+                    unsafe { libc::kill(target, libc::SIGKILL) };
+                }
+            })
+        });
+
+        Watch {
+            _active: self.active,
+            handle,
+            signal_thread,
+            done,
+            timer_thread,
+            cancelled,
+            timed_out,
+        }
+    }
+}
+
+pub struct Watch {
+    _active: Active,
+    handle: Handle,
+    signal_thread: JoinHandle<()>,
+    done: Sender<()>,
+    timer_thread: Option<JoinHandle<()>>,
+    cancelled: Arc<AtomicI32>,
+    timed_out: Arc<AtomicBool>,
+}
+
+impl Watch {
+    // Call this once the command has been waited for
+    pub fn finish(self) -> Termination {
+        self.handle.close();
+        self.done.send(()).ok();
+        // Neither thread has anything to report, even if it panicked
+        self.signal_thread.join().ok();
+
+        if let Some(timer_thread) = self.timer_thread {
+            timer_thread.join().ok();
+        }
+
+        if self.timed_out.load(Ordering::SeqCst) {
+            Termination::TimedOut
+        } else {
+            match self.cancelled.load(Ordering::SeqCst) {
+                0 => Termination::Exited,
+                signal => Termination::Cancelled(signal),
+            }
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use crate::utils::{escape_double_quoted, escape_single_quoted, shell_quote};
+
+// How a value is written into the command
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    // Escaped for wherever the placeholder sits: bare, or inside quotes
+    Auto,
+    // Inserted as is, so it can add shell syntax of its own
+    Raw,
+    // Always inserted as one single-quoted word
+    ShellQuote,
+}
+
+impl Filter {
+    fn from_name(name: Option<&str>) -> Option<Self> {
+        match name {
+            None => Some(Filter::Auto),
+            Some("raw") => Some(Filter::Raw),
+            Some("shellquote") => Some(Filter::ShellQuote),
+            Some(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Quote {
+    None,
+    Single,
+    Double,
+}
+
+// A `{name}` or `{name|filter}` placeholder (`{}` has an empty name)
+struct Placeholder<'a> {
+    name: &'a str,
+    filter: Filter,
+    len: usize,
+}
+
+fn parse_placeholder(s: &str) -> Option<Placeholder<'_>> {
+    let end = s.find('}')?;
+    let inner = &s[1..end];
+    let (name, filter) = match inner.split_once('|') {
+        Some((name, filter)) => (name, Some(filter)),
+        None => (inner, None),
+    };
+    let is_name = name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'));
+
+    if !is_name {
+        return None;
+    }
+
+    Some(Placeholder {
+        name,
+        filter: Filter::from_name(filter)?,
+        len: end + 1,
+    })
+}
+
+// This function replaces the placeholders in a command with the values
+// `lookup` returns, escaping each one for the quotes around it. Anything
+// that isn't a known placeholder (`${HOME}`, `awk '{print $1}'`, unknown
+// names or filters) is left untouched.
+pub fn render<F>(template: &str, mut lookup: F) -> String
+where
+    F: FnMut(&str) -> Option<String>,
+{
+    let mut result = String::new();
+    let mut quote = Quote::None;
+    let mut escaped = false;
+    let mut prev = None;
+    let mut i = 0;
+
+    while let Some(c) = template[i..].chars().next() {
+        if c == '{' && !escaped && prev != Some('$') {
+            let placeholder = parse_placeholder(&template[i..])
+                .and_then(|p| lookup(p.name).map(|value| (p, value)));
+
+            if let Some((placeholder, value)) = placeholder {
+                result.push_str(&escape(&value, placeholder.filter, quote));
+                i += placeholder.len;
+                prev = Some('}');
+                continue;
+            }
+        }
+
+        match (c, quote) {
+            _ if escaped => escaped = false,
+            ('\\', Quote::None | Quote::Double) => escaped = true,
+            ('\'', Quote::None) => quote = Quote::Single,
+            ('\'', Quote::Single) => quote = Quote::None,
+            ('"', Quote::None) => quote = Quote::Double,
+            ('"', Quote::Double) => quote = Quote::None,
+            _ => {}
+        }
+
+        result.push(c);
+        prev = Some(c);
+        i += c.len_utf8();
+    }
+
+    result
+}
+
+fn escape(value: &str, filter: Filter, quote: Quote) -> String {
+    match (filter, quote) {
+        (Filter::Raw, _) => value.to_owned(),
+        (Filter::ShellQuote, _) | (Filter::Auto, Quote::None) => shell_quote(value),
+        (Filter::Auto, Quote::Single) => escape_single_quoted(value),
+        (Filter::Auto, Quote::Double) => escape_double_quoted(value),
+    }
+}
@@ -29,6 +29,30 @@ impl<'a, C: TermCursor> TermWriter<'a, C> {
         }
     }
 
+    // Draws the input that was there to begin with on the current line, with
+    // the cursor after it
+    pub fn show(&mut self) -> Result<(), InputError> {
+        let cursor_pos = self.stdout.get_cursor_pos()?;
+
+        self.cursor_pos.x = self.input.len() as u16 + 1;
+        self.cursor_pos.y = cursor_pos.1;
+
+        self.stdout.write_term(format_args!(
+            "{}{}{}",
+            termion::cursor::Goto(1, self.cursor_pos.y),
+            termion::clear::CurrentLine,
+            self.input,
+        ))?;
+
+        Ok(())
+    }
+
+    // Swaps the whole input for another, e.g. from the history
+    pub fn replace(&mut self, input: String) -> Result<(), InputError> {
+        self.input = input;
+        self.show()
+    }
+
     pub fn enter(self) -> Result<Input, InputError> {
         if self.input.trim().is_empty() {
             Err(InputError::EmptyString)
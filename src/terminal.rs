@@ -0,0 +1,121 @@
+use std::{
+    io,
+    mem::MaybeUninit,
+    panic,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    },
+};
+
+use signal_hook::{
+    consts::{SIGHUP, SIGINT, SIGQUIT, SIGTERM},
+    low_level,
+};
+
+use crate::supervisor;
+
+const SHOW_CURSOR: &[u8] = b"\x1b[?25h";
+const TO_MAIN_SCREEN: &[u8] = b"\x1b[?1049l";
+
+// The terminal's settings from before anything touched them
+static ORIGINAL_TERMIOS: OnceLock<libc::termios> = OnceLock::new();
+static ALTERNATE_SCREEN: AtomicBool = AtomicBool::new(false);
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+// This guard puts the terminal back the way it found it (cooked mode,
+// visible cursor, main screen) when it's dropped, on panic, on a fatal
+// signal and when the process exits
+pub struct TerminalGuard {
+    _private: (),
+}
+
+impl TerminalGuard {
+    pub fn new() -> io::Result<Self> {
+        save_termios();
+
+        if !INSTALLED.swap(true, Ordering::SeqCst) {
+            install_panic_hook();
+            install_signal_handlers()?;
+            unsafe { libc::atexit(restore_at_exit) };
+        }
+
+        Ok(Self { _private: () })
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        restore();
+    }
+}
+
+// Keeps track of whether `restore` has to leave the alternate screen
+pub fn set_alternate_screen(active: bool) {
+    ALTERNATE_SCREEN.store(active, Ordering::SeqCst);
+}
+
+// This function may run inside a signal handler, so it only makes
+// async-signal-safe calls
+pub fn restore() {
+    if let Some(termios) = ORIGINAL_TERMIOS.get() {
+        unsafe { libc::tcsetattr(libc::STDOUT_FILENO, libc::TCSANOW, termios) };
+    }
+
+    if ALTERNATE_SCREEN.swap(false, Ordering::SeqCst) {
+        write_stdout(TO_MAIN_SCREEN);
+    }
+
+    if unsafe { libc::isatty(libc::STDOUT_FILENO) } == 1 {
+        write_stdout(SHOW_CURSOR);
+    }
+}
+
+fn write_stdout(bytes: &[u8]) {
+    unsafe { libc::write(libc::STDOUT_FILENO, bytes.as_ptr().cast(), bytes.len()) };
+}
+
+fn save_termios() {
+    if ORIGINAL_TERMIOS.get().is_some() {
+        return;
+    }
+
+    let mut termios = MaybeUninit::<libc::termios>::uninit();
+
+    if unsafe { libc::tcgetattr(libc::STDOUT_FILENO, termios.as_mut_ptr()) } == 0 {
+        ORIGINAL_TERMIOS.set(unsafe { termios.assume_init() }).ok();
+    }
+}
+
+fn install_panic_hook() {
+    let hook = panic::take_hook();
+
+    panic::set_hook(Box::new(move |info| {
+        // Otherwise the message would be printed in raw mode
+        restore();
+        hook(info);
+    }));
+}
+
+fn install_signal_handlers() -> io::Result<()> {
+    for signal in [SIGHUP, SIGINT, SIGQUIT, SIGTERM] {
+        let action = move || {
+            // A running command owns the terminal and gets the signal
+            // forwarded instead, and we wait for it to finish
+            if supervisor::is_supervising() {
+                return;
+            }
+
+            restore();
+            low_level::emulate_default_handler(signal).ok();
+        };
+
+        unsafe { low_level::register(signal, action)? };
+    }
+
+    Ok(())
+}
+
+extern "C" fn restore_at_exit() {
+    restore();
+}
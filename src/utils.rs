@@ -1,8 +1,42 @@
-pub fn escape_backticks(input: &str) -> String {
+// This function quotes a string for a POSIX shell so it is always read as a
+// single literal word
+pub fn shell_quote(input: &str) -> String {
+    if is_plain_word(input) {
+        return input.to_owned();
+    }
+
+    format!("'{}'", escape_single_quoted(input))
+}
+
+// Like `shell_quote`, but for fish, where backslashes in single quotes
+// escape too
+pub fn fish_quote(input: &str) -> String {
+    if is_plain_word(input) {
+        return input.to_owned();
+    }
+
+    format!("'{}'", input.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+// Words that mean the same quoted or not, in any shell
+fn is_plain_word(input: &str) -> bool {
+    !input.is_empty()
+        && input
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c))
+}
+
+// Escapes a string that goes between single quotes
+pub fn escape_single_quoted(input: &str) -> String {
+    input.replace('\'', "'\\''")
+}
+
+// Escapes a string that goes between double quotes
+pub fn escape_double_quoted(input: &str) -> String {
     let mut result = String::new();
 
     for c in input.chars() {
-        if c == '`' {
+        if matches!(c, '"' | '\\' | '$' | '`') {
             result.push('\\');
         }
         result.push(c);
@@ -10,6 +44,67 @@ pub fn escape_backticks(input: &str) -> String {
     result
 }
 
-pub fn starts_with_any(s: &str, pats: &[&str]) -> bool {
-    pats.iter().any(|pat| s.starts_with(pat))
+// This function splits a command into words the way a POSIX shell would,
+// honoring single quotes, double quotes and backslashes (no expansions)
+pub fn split_words(s: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_word = true;
+
+                for c in chars.by_ref() {
+                    if c == '\'' {
+                        break;
+                    }
+                    word.push(c);
+                }
+            }
+            '"' => {
+                in_word = true;
+
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => match chars.next() {
+                            Some(next @ ('"' | '\\' | '$' | '`')) => word.push(next),
+                            Some(next) => {
+                                word.push('\\');
+                                word.push(next);
+                            }
+                            None => word.push('\\'),
+                        },
+                        _ => word.push(c),
+                    }
+                }
+            }
+            '\\' => {
+                in_word = true;
+
+                if let Some(next) = chars.next() {
+                    word.push(next);
+                }
+            }
+            c if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            _ => {
+                in_word = true;
+                word.push(c);
+            }
+        }
+    }
+
+    if in_word {
+        words.push(word);
+    }
+
+    words
 }
@@ -1,29 +1,47 @@
-use keymap::{
-    keymap::Keymap,
+use quicommand::{
+    audit::{AuditLog, Filter, Frecency, Record, REDACTED},
+    cli::{self, Command, ListFormat, Print},
+    cmd_runner::{CmdRunner, CmdType, OutputMode, INTERACTIVE_PROGRAMS},
+    config::{editor_template, Config, DEFAULT_EDITOR},
+    config_file::{self, ConfigFile},
+    context::Context,
+    input::Input,
+    keymap::{self, Condition, Keymap, Order, PassInput},
+    keymap_editor::{Action, KeymapEditor},
     mock_stdout::MockStdout,
+    output::{Chunk, Output, OutputEvent, Retention, Stream},
+    pager::Pager,
+    quickfix::{self, Location, Picker},
     screen::Screen,
+    shell::{Shell, Syntax},
+    stats::Stats,
     step::{Process, Step},
+    supervisor::Termination,
+    template, Error,
 };
 
+use chrono::{DateTime, Local};
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+use std::time::{Duration, Instant};
 use termion::event::Key;
 
-fn get_keymaps<'a>() -> Vec<Keymap> {
+fn get_keymaps() -> Vec<Keymap> {
     vec![Keymap::new('t', "echo 'test'")]
 }
 
-fn get_keymaps_with_description<'a>() -> Vec<Keymap> {
+fn get_keymaps_with_description() -> Vec<Keymap> {
     vec![Keymap::new('t', "echo {}").with_description("Test description")]
 }
 
-fn get_keymaps_with_prompt<'a>() -> Vec<Keymap> {
+fn get_keymaps_with_prompt() -> Vec<Keymap> {
     vec![Keymap::new('t', "echo {}").with_prompt("Test prompt")]
 }
 
 fn setup_step() -> Step<MockStdout> {
     let stdout = MockStdout::new();
     let screen = Screen::new(stdout);
-    let step = Step::new(screen);
-    step
+    Step::new(screen)
 }
 
 #[test]
@@ -31,7 +49,7 @@ fn keymap() {
     let keymaps = get_keymaps();
     let mut step = setup_step();
 
-    step.show_select_cmd(&keymaps);
+    step.show_select_cmd(&keymaps).unwrap();
 
     let stdout_str = String::from_utf8(step.screen.stdout.buffer).unwrap();
     let has_prompt = stdout_str.contains("Please select a command:");
@@ -45,7 +63,7 @@ fn keymap_with_description() {
     let keymaps = get_keymaps_with_description();
     let mut step = setup_step();
 
-    step.show_select_cmd(&keymaps);
+    step.show_select_cmd(&keymaps).unwrap();
 
     let stdout_str = String::from_utf8(step.screen.stdout.buffer).unwrap();
     let has_prompt = stdout_str.contains("Please select a command:");
@@ -64,12 +82,12 @@ fn cmd() {
     let result = output.unwrap();
 
     let Process::Output(output) = result else {
-      panic!();
+        panic!();
     };
 
-    let stdout_str = String::from_utf8_lossy(&output.stdout);
+    let stdout_str = String::from_utf8_lossy(&output.stdout()).into_owned();
 
-    assert_eq!(stdout_str, "test");
+    assert_eq!(stdout_str, "test\n");
 }
 
 #[test]
@@ -89,12 +107,12 @@ fn cmd_with_input() {
     let result = output.unwrap();
 
     let Process::Output(output) = result else {
-      panic!();
+        panic!();
     };
 
-    let stdout_str = String::from_utf8_lossy(&output.stdout);
+    let stdout_str = String::from_utf8_lossy(&output.stdout()).into_owned();
 
-    assert_eq!(stdout_str, "test");
+    assert_eq!(stdout_str, "test\n");
 }
 
 #[test]
@@ -111,6 +129,60 @@ fn cmd_with_prompt() {
     assert!(stdout_str.contains("Test prompt"));
 }
 
+#[test]
+fn cmd_is_audited() {
+    let path = std::env::temp_dir().join(format!("quicommand-audit-{}.jsonl", std::process::id()));
+    let audit_log = AuditLog::new(&path);
+    let keymaps = [
+        Keymap::new('t', "echo {}")
+            .with_prompt("Password:")
+            .with_secret(true),
+        Keymap::new('f', "false"),
+    ];
+
+    for (keymap, keys) in keymaps.iter().zip([vec![Ok(Key::Char('x'))], Vec::new()]) {
+        let mut step =
+            setup_step().with_config(Config::default().with_audit_log(audit_log.clone()));
+        let input = step.input_from_prompt(keymap.prompt.as_deref(), keys.into_iter());
+
+        step.process_input(input, keymap).unwrap();
+    }
+
+    let records = audit_log.records(&Filter::default()).unwrap();
+    let failed = Filter {
+        failed: true,
+        ..Default::default()
+    };
+
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].cmd, format!("echo '{}'", REDACTED));
+    assert_eq!(records[0].key, 't');
+    assert_eq!(records[0].input, None);
+    assert_eq!(records[0].exit_code, 0);
+    // The command itself got the input, and printed "x\n"
+    assert_eq!(
+        records[0].output_sha256,
+        "73cb3858a687a8494ca3323053016282f3dad39d42cf62ca4e79dda2aac7d9ac"
+    );
+    assert_eq!(audit_log.records(&failed).unwrap(), [records[1].clone()]);
+
+    // `{env.NAME}` values are left out whatever the keymap, and `{date}` is
+    // the same in both since it's only looked up once
+    let keymap = Keymap::new('u', "curl -u {env.HOME}:{} -H {date}")
+        .with_prompt("Password:")
+        .with_secret(true);
+    let (cmd, logged_cmd) = keymap.render_cmds(Some("hunter2"), &Context::default());
+    let date = cmd.rsplit(' ').next().unwrap();
+
+    assert!(cmd.contains("hunter2"));
+    assert_eq!(
+        logged_cmd,
+        format!("curl -u '{}':'{}' -H {}", REDACTED, REDACTED, date)
+    );
+
+    std::fs::remove_file(path).unwrap();
+}
+
 #[test]
 fn cmd_with_empty_input() {
     let mut step = setup_step();
@@ -136,3 +208,1614 @@ fn cmd_with_cancelled_input() {
 
     assert!(matches!(output.unwrap(), Process::Exit));
 }
+
+#[test]
+fn cmd_type_matches_first_word() {
+    let detect = |cmd| CmdType::detect(cmd, INTERACTIVE_PROGRAMS);
+
+    assert_eq!(detect("vim src/main.rs"), CmdType::Interactive);
+    assert_eq!(detect("nvim"), CmdType::Interactive);
+    assert_eq!(
+        detect("EDITOR=hx /usr/bin/less README.md"),
+        CmdType::Interactive
+    );
+    assert_eq!(detect("git rebase -i HEAD~3"), CmdType::Interactive);
+    assert_eq!(detect("view README.md"), CmdType::Script);
+    assert_eq!(detect("fzfx"), CmdType::Script);
+    assert_eq!(detect("git rebase main"), CmdType::Script);
+}
+
+#[test]
+fn cmd_type_from_config() {
+    let config = Config::default().with_interactive_programs(&["view"]);
+    let keymap = Keymap::new('v', "view README.md");
+
+    assert_eq!(config.cmd_type(&keymap, &keymap.cmd), CmdType::Interactive);
+
+    let keymap = Keymap::new('v', "vim README.md");
+
+    assert_eq!(config.cmd_type(&keymap, &keymap.cmd), CmdType::Script);
+}
+
+#[test]
+fn cmd_type_from_keymap() {
+    let config = Config::default();
+    let keymap = Keymap::new('l', "less README.md").with_interactive(false);
+
+    assert_eq!(config.cmd_type(&keymap, &keymap.cmd), CmdType::Script);
+
+    let keymap = Keymap::new('p', "python3").with_interactive(true);
+
+    assert_eq!(config.cmd_type(&keymap, &keymap.cmd), CmdType::Interactive);
+}
+
+#[test]
+fn cmd_runs_in_tty() {
+    let mut cmd_runner = CmdRunner::new("test -t 1 && test -t 2 && echo tty; echo err >&2");
+    let output = cmd_runner.run_with_output().unwrap();
+
+    assert_eq!(String::from_utf8_lossy(&output.stdout()), "tty\n");
+    assert_eq!(String::from_utf8_lossy(&output.stderr()), "err\n");
+}
+
+#[test]
+fn cmd_runs_in_pipes() {
+    let mut cmd_runner =
+        CmdRunner::new("test -t 1 || echo pipe").with_output_mode(OutputMode::Pipe);
+    let output = cmd_runner.run_with_output().unwrap();
+
+    assert_eq!(String::from_utf8_lossy(&output.stdout()), "pipe\n");
+}
+
+#[test]
+fn cmd_with_shell() {
+    let shell = "sh -c".parse::<Shell>().unwrap();
+    let mut cmd_runner = CmdRunner::new("echo $0").with_shell(shell);
+
+    assert_eq!(cmd_runner.argv(), ["sh", "-c", "echo $0"]);
+
+    let output = cmd_runner.run_with_output().unwrap();
+
+    assert_eq!(String::from_utf8_lossy(&output.stdout()), "sh\n");
+}
+
+#[test]
+fn cmd_without_shell() {
+    let shell = "none".parse::<Shell>().unwrap();
+    let mut cmd_runner = CmdRunner::new("echo '$HOME  literally'").with_shell(shell);
+
+    assert_eq!(cmd_runner.argv(), ["echo", "$HOME  literally"]);
+
+    let output = cmd_runner.run_with_output().unwrap();
+
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout()),
+        "$HOME  literally\n"
+    );
+}
+
+#[test]
+fn shell_from_keymap() {
+    let config = Config::default().with_shell(Shell::None);
+    let keymap = Keymap::new('t', "echo test");
+
+    assert_eq!(config.shell(&keymap), &Shell::None);
+
+    let keymap = keymap.with_shell("bash -lc".parse().unwrap());
+
+    assert_eq!(config.shell(&keymap).to_string(), "bash -lc");
+}
+
+fn render(cmd: &str, input: &str) -> String {
+    Keymap::new('t', cmd)
+        .with_prompt_name("msg")
+        .render_cmd(Some(input), &Context::default())
+}
+
+#[test]
+fn placeholder_is_quoted_for_its_context() {
+    let input = "it's \"$(rm -rf ~)\"; `id`";
+
+    assert_eq!(render("echo {}", "test"), "echo test");
+    assert_eq!(
+        render("echo {}", input),
+        r#"echo 'it'\''s "$(rm -rf ~)"; `id`'"#
+    );
+    assert_eq!(
+        render("git commit -m \"{msg}\"", input),
+        r#"git commit -m "it's \"\$(rm -rf ~)\"; \`id\`""#
+    );
+    assert_eq!(
+        render("echo '{}'", input),
+        r#"echo 'it'\''s "$(rm -rf ~)"; `id`'"#
+    );
+}
+
+#[test]
+fn placeholder_with_filter() {
+    assert_eq!(render("echo {msg|raw}", "$HOME"), "echo $HOME");
+    assert_eq!(render("echo \"{msg|shellquote}\"", "a b"), "echo \"'a b'\"");
+}
+
+#[test]
+fn non_placeholders_are_left_alone() {
+    let cmd = "echo ${HOME} {unknown} {msg|upper} \\{msg} && awk '{print $1}'";
+
+    assert_eq!(render(cmd, "x"), cmd);
+    assert_eq!(
+        template::render("{a}{b}", |name| Some(name.repeat(2))),
+        "aabb"
+    );
+}
+
+fn run_with_keys(keymap: &Keymap, input: &str) -> String {
+    let mut step = setup_step();
+    let keys: Vec<_> = input.chars().map(|c| Ok(Key::Char(c))).collect();
+    let input = step.input_from_prompt(keymap.prompt.as_deref(), keys.into_iter());
+
+    let Process::Output(output) = step.process_input(input, keymap).unwrap() else {
+        panic!();
+    };
+
+    String::from_utf8_lossy(&output.stdout()).into_owned()
+}
+
+#[test]
+fn cmd_with_injected_input() {
+    let keymap = Keymap::new('t', "printf '%s|' {} \"{}\" '{}'").with_prompt("Test prompt");
+    let input = "$(echo pwned)';\"`echo pwned`";
+
+    assert_eq!(
+        run_with_keys(&keymap, input),
+        format!("{input}|{input}|{input}|")
+    );
+}
+
+#[test]
+fn cmd_with_input_in_env() {
+    let keymap = Keymap::new('t', "printf %s \"$QC_COMMIT_MSG\"")
+        .with_prompt("Test prompt")
+        .with_prompt_name("commit-msg")
+        .with_pass_input(PassInput::Env);
+
+    assert_eq!(keymap.prompt_env_var(), "QC_COMMIT_MSG");
+    assert_eq!(run_with_keys(&keymap, "it's $(id)"), "it's $(id)");
+}
+
+#[test]
+fn cmd_with_input_in_stdin() {
+    let keymap = Keymap::new('t', "read -r line; printf %s \"$line\"")
+        .with_prompt("Test prompt")
+        .with_pass_input(PassInput::Stdin);
+
+    assert_eq!(run_with_keys(&keymap, "\"quoted\" `id`"), "\"quoted\" `id`");
+}
+
+#[test]
+fn cmd_with_timeout() {
+    let step = setup_step();
+    let keymap =
+        Keymap::new('t', "echo start; sleep 10; echo end").with_timeout(Duration::from_millis(300));
+    let start = Instant::now();
+    let input = Ok(Input::None);
+
+    let Process::TimedOut(output) = step.process_input(input, &keymap).unwrap() else {
+        panic!();
+    };
+
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert_eq!(String::from_utf8_lossy(&output.stdout()), "start\n");
+}
+
+#[test]
+fn cmd_with_timeout_in_pipes() {
+    let mut cmd_runner = CmdRunner::new("sleep 10 & wait")
+        .with_output_mode(OutputMode::Pipe)
+        .with_timeout(Duration::from_millis(300));
+    let start = Instant::now();
+
+    cmd_runner.run_with_output().unwrap();
+
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert_eq!(cmd_runner.termination, Termination::TimedOut);
+}
+
+#[test]
+fn cmd_that_cannot_be_spawned() {
+    let shell = "/nonexistent/shell -c".parse().unwrap();
+    let mut cmd_runner = CmdRunner::new("echo test").with_shell(shell);
+    let error = cmd_runner.run_with_output().unwrap_err();
+
+    assert!(matches!(error, Error::Spawn { .. }));
+    assert_eq!(error.exit_code(), 127);
+    assert!(error.to_string().starts_with("Failed to run `echo test`"));
+}
+
+#[test]
+fn empty_shell_is_a_config_error() {
+    let error = "".parse::<Shell>().unwrap_err();
+
+    assert!(matches!(error, Error::Config(_)));
+    assert_eq!(error.exit_code(), 78);
+}
+
+fn output_with_status(status: ExitStatus) -> Output {
+    Output::new(status, Vec::new())
+}
+
+#[test]
+fn status_line() {
+    let elapsed = Duration::from_millis(3240);
+    let success = Process::Output(output_with_status(ExitStatus::from_raw(0)));
+    let failure = Process::Output(output_with_status(ExitStatus::from_raw(101 << 8)));
+    let killed = Process::Output(output_with_status(ExitStatus::from_raw(9)));
+
+    assert_eq!(success.status_line(elapsed).unwrap(), "✔ exit 0 in 3.2s");
+    assert_eq!(failure.status_line(elapsed).unwrap(), "✘ exit 101 in 3.2s");
+    assert_eq!(
+        killed.status_line(elapsed).unwrap(),
+        "✘ killed by signal 9 in 3.2s"
+    );
+    assert_eq!(Process::Exit.status_line(elapsed), None);
+}
+
+#[test]
+fn exit_code_mirrors_cmd() {
+    let step = setup_step();
+    let keymap = Keymap::new('t', "exit 3");
+    let process = step.process_input(Ok(Input::None), &keymap).unwrap();
+
+    assert_eq!(process.exit_code(), 3);
+    assert_eq!(
+        Process::Output(output_with_status(ExitStatus::from_raw(9))).exit_code(),
+        137
+    );
+    assert_eq!(
+        Process::TimedOut(output_with_status(ExitStatus::from_raw(15))).exit_code(),
+        124
+    );
+    assert_eq!(
+        Process::Cancelled(output_with_status(ExitStatus::from_raw(15)), 15).exit_code(),
+        143
+    );
+    // Logs from before the signal was recorded still load
+    assert_eq!(
+        "cancelled".parse::<Termination>().unwrap(),
+        Termination::Cancelled(2)
+    );
+    assert_eq!(
+        serde_json::to_string(&Termination::Cancelled(1)).unwrap(),
+        "\"cancelled:1\""
+    );
+
+    // SIGTERM is passed on to the command, and quicommand exits like it
+    // was stopped by it
+    let dir = std::env::temp_dir().join(format!("quicommand-sigterm-{}", std::process::id()));
+
+    std::fs::create_dir_all(dir.join("quicommand")).unwrap();
+    std::fs::write(
+        dir.join("quicommand/config.toml"),
+        "[[keymap]]\nkey = \"s\"\ncmd = \"echo started; sleep 10\"\n",
+    )
+    .unwrap();
+
+    let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin("quicommand"))
+        .args(["run", "s"])
+        .current_dir(&dir)
+        .env("XDG_STATE_HOME", &dir)
+        .env("XDG_CONFIG_HOME", &dir)
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut line = String::new();
+
+    // The command only starts once quicommand is handling signals
+    std::io::BufRead::read_line(
+        &mut std::io::BufReader::new(child.stdout.take().unwrap()),
+        &mut line,
+    )
+    .unwrap();
+    std::process::Command::new("kill")
+        .args(["-TERM", &child.id().to_string()])
+        .status()
+        .unwrap();
+
+    assert_eq!(line, "started\n");
+    assert_eq!(child.wait().unwrap().code(), Some(143));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn output_is_captured_byte_for_byte() {
+    for output_mode in [OutputMode::Pty, OutputMode::Pipe] {
+        let mut cmd_runner =
+            CmdRunner::new(r"printf 'line1\nline2\r\n\377\n'").with_output_mode(output_mode);
+        let output = cmd_runner.run_with_output().unwrap();
+
+        assert_eq!(output.stdout(), b"line1\nline2\r\n\xff\n");
+    }
+}
+
+#[test]
+fn output_keeps_stream_order() {
+    let cmd = "echo out1; sleep 0.1; echo err1 >&2; sleep 0.1; echo out2";
+    let mut cmd_runner = CmdRunner::new(cmd);
+    let output = cmd_runner.run_with_output().unwrap();
+
+    let streams: Vec<_> = output.combined.iter().map(|chunk| chunk.stream).collect();
+
+    assert_eq!(streams, [Stream::Stdout, Stream::Stderr, Stream::Stdout]);
+    assert_eq!(output.combined_bytes(), b"out1\nerr1\nout2\n");
+    assert!(output.combined.windows(2).all(|w| w[0].at <= w[1].at));
+    assert_eq!(output.stdout(), b"out1\nout2\n");
+    assert_eq!(output.stderr(), b"err1\n");
+}
+
+#[test]
+fn output_as_events() {
+    let mut cmd_runner = CmdRunner::new("echo out; sleep 0.1; echo err >&2; exit 2");
+    let mut stream = cmd_runner.stream().unwrap();
+    let events: Vec<_> = stream.by_ref().collect();
+
+    assert_eq!(events[0], OutputEvent::Stdout(b"out\n".to_vec()));
+    assert_eq!(events[1], OutputEvent::Stderr(b"err\n".to_vec()));
+    assert!(matches!(events[2], OutputEvent::Exit(status) if status.code() == Some(2)));
+    assert_eq!(events.len(), 3);
+    assert_eq!(stream.finish().unwrap(), Termination::Exited);
+}
+
+#[test]
+fn output_events_arrive_while_running() {
+    let mut cmd_runner = CmdRunner::new("echo first; sleep 10")
+        .with_output_mode(OutputMode::Pipe)
+        .with_timeout(Duration::from_secs(1));
+    let mut stream = cmd_runner.stream().unwrap();
+    let start = Instant::now();
+
+    assert_eq!(
+        stream.next(),
+        Some(OutputEvent::Stdout(b"first\n".to_vec()))
+    );
+    assert!(start.elapsed() < Duration::from_millis(900));
+    assert_eq!(stream.finish().unwrap(), Termination::TimedOut);
+}
+
+fn pager_output() -> Output {
+    let chunk = |stream, bytes: &str| Chunk {
+        stream,
+        at: Duration::ZERO,
+        bytes: bytes.as_bytes().to_vec(),
+    };
+    let combined = vec![
+        chunk(Stream::Stdout, "one\ntwo\n\x1b[31mthr"),
+        chunk(Stream::Stderr, "warning: four\n"),
+        chunk(Stream::Stdout, "ee\x1b[0m\nfive\n"),
+        chunk(Stream::Stdout, "10%\r100%\nsix\n"),
+    ];
+
+    Output::new(ExitStatus::from_raw(0), combined)
+}
+
+#[test]
+fn pager_lines() {
+    let mut pager = Pager::new(&pager_output(), (80, 3));
+    let texts = |pager: &Pager| -> Vec<String> {
+        pager
+            .visible_lines()
+            .iter()
+            .map(|line| line.text.clone())
+            .collect()
+    };
+
+    assert_eq!(
+        texts(&pager),
+        [
+            "one",
+            "two",
+            "warning: four",
+            "three",
+            "five",
+            "100%",
+            "six"
+        ]
+    );
+
+    pager.key(Key::Char('s'));
+
+    assert_eq!(
+        texts(&pager),
+        ["one", "two", "three", "five", "100%", "six"]
+    );
+}
+
+#[test]
+fn pager_keys() {
+    let mut pager = Pager::new(&pager_output(), (80, 3));
+
+    for (key, top) in [
+        (Key::Char('j'), 1),
+        (Key::PageDown, 3),
+        (Key::PageDown, 5),
+        (Key::Char('j'), 5),
+        (Key::Char('k'), 4),
+        (Key::PageUp, 2),
+        (Key::Char('g'), 0),
+        (Key::Char('G'), 5),
+    ] {
+        pager.key(key);
+
+        assert_eq!(pager.top(), top, "after {:?}", key);
+    }
+
+    assert!(!pager.key(Key::Char('q')));
+}
+
+#[test]
+fn pager_search() {
+    let mut pager = Pager::new(&pager_output(), (80, 3));
+    let mut stdout = MockStdout::new();
+
+    for c in "/f".chars() {
+        pager.key(Key::Char(c));
+    }
+
+    pager.render(&mut stdout).unwrap();
+
+    assert!(String::from_utf8_lossy(&stdout.buffer).ends_with("/f\u{1b}[m"));
+
+    pager.key(Key::Char('\n'));
+
+    // Only scrolled as far as it takes to show the match
+    assert_eq!((pager.current_match(), pager.top()), (Some(2), 1));
+
+    pager.key(Key::Char('n'));
+
+    assert_eq!((pager.current_match(), pager.top()), (Some(4), 3));
+
+    pager.key(Key::Char('N'));
+
+    assert_eq!((pager.current_match(), pager.top()), (Some(2), 2));
+
+    let mut stdout = MockStdout::new();
+
+    pager.render(&mut stdout).unwrap();
+
+    assert!(String::from_utf8_lossy(&stdout.buffer).contains("warning: \u{1b}[7mf\u{1b}[27mour"));
+
+    // Matches on the last page, which can't scroll any further, are still
+    // gone through one by one
+    let mut pager = Pager::new(&pager_output(), (80, 5));
+
+    for c in "/i\n".chars() {
+        pager.key(Key::Char(c));
+    }
+
+    let mut matches = vec![pager.current_match()];
+
+    for c in "nnnNN".chars() {
+        pager.key(Key::Char(c));
+        matches.push(pager.current_match());
+    }
+
+    assert_eq!(
+        matches,
+        [Some(2), Some(4), Some(6), Some(6), Some(4), Some(2)]
+    );
+    assert_eq!(pager.top(), 2);
+}
+
+#[test]
+fn quickfix_locations() {
+    let text = "\
+   Compiling app v0.1.0
+error[E0308]: mismatched types
+  --> src/main.rs:10:5
+   |
+main.c:3:14: error: expected ';' before '}' token
+src/app.ts(7,1): error TS2304: Cannot find name 'x'.
+src/app.ts:7:1 - error TS2304: Cannot find name 'x'.
+/home/alex/web/index.js
+  12:8  warning  'y' is assigned a value but never used  no-unused-vars
+warning: `app` (bin \"app\") generated 1 warning
+";
+    let chunk = Chunk {
+        stream: Stream::Stderr,
+        at: Duration::ZERO,
+        bytes: text.as_bytes().to_vec(),
+    };
+    let output = Output::new(ExitStatus::from_raw(256), vec![chunk]);
+    let location = |path: &str, line, column, message: &str| Location {
+        path: path.to_owned(),
+        line,
+        column,
+        message: message.to_owned(),
+    };
+
+    assert_eq!(
+        quickfix::locations(&output),
+        [
+            location("src/main.rs", 10, 5, "error[E0308]: mismatched types"),
+            location("main.c", 3, 14, "error: expected ';' before '}' token"),
+            location("src/app.ts", 7, 1, "error TS2304: Cannot find name 'x'."),
+            location(
+                "/home/alex/web/index.js",
+                12,
+                8,
+                "warning  'y' is assigned a value but never used  no-unused-vars"
+            ),
+        ]
+    );
+}
+
+#[test]
+fn quickfix_editor() {
+    let locations = [
+        Location {
+            path: "src/main.rs".to_owned(),
+            line: 10,
+            column: 5,
+            message: String::new(),
+        },
+        Location {
+            path: "my file.c".to_owned(),
+            line: 3,
+            column: 14,
+            message: String::new(),
+        },
+    ];
+    let mut picker = Picker::new(&locations, (80, 24));
+    let mut stdout = MockStdout::new();
+    let keys = vec![Ok(Key::Char('j')), Ok(Key::Char('j')), Ok(Key::Char('\n'))];
+    let location = picker.run(&mut stdout, keys.into_iter()).unwrap().unwrap();
+
+    assert_eq!(picker.selected(), 1);
+    assert_eq!(
+        Config::default()
+            .with_editor(DEFAULT_EDITOR)
+            .editor_cmd(location),
+        "hx 'my file.c':3"
+    );
+    assert_eq!(
+        Config::default()
+            .with_editor("vim +{line} {file}")
+            .editor_cmd(&locations[0]),
+        "vim +10 src/main.rs"
+    );
+    assert_eq!(
+        editor_template("/usr/bin/nvim"),
+        "/usr/bin/nvim +{line} {file}"
+    );
+    assert_eq!(
+        editor_template("code --wait"),
+        "code --wait --goto {file}:{line}:{column}"
+    );
+    assert_eq!(editor_template("ed"), "ed {file}");
+
+    let file = ConfigFile::parse("c.toml", "quickfix = true").unwrap();
+
+    assert!(!Config::default().quickfix);
+    assert!(file.apply(Config::default()).quickfix);
+}
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+#[test]
+fn cli_commands() {
+    let run = |target: &str, values: &[(&str, &str)]| Command::Run {
+        target: target.to_owned(),
+        args: values
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect(),
+    };
+
+    assert_eq!(
+        cli::parse(&[]).unwrap(),
+        Command::Palette {
+            pager: false,
+            edit: false,
+            dry_run: false,
+            print: None
+        }
+    );
+    assert_eq!(
+        cli::parse(&args(&["--pager"])).unwrap(),
+        Command::Palette {
+            pager: true,
+            edit: false,
+            dry_run: false,
+            print: None
+        }
+    );
+    assert_eq!(
+        cli::parse(&args(&["--edit"])).unwrap(),
+        Command::Palette {
+            pager: false,
+            edit: true,
+            dry_run: false,
+            print: None
+        }
+    );
+    assert_eq!(
+        cli::parse(&args(&["--dry-run", "--pager"])).unwrap(),
+        Command::Palette {
+            pager: true,
+            edit: false,
+            dry_run: true,
+            print: None
+        }
+    );
+    assert_eq!(
+        cli::parse(&args(&["--print"])).unwrap(),
+        Command::Palette {
+            pager: false,
+            edit: false,
+            dry_run: false,
+            print: Some(Print {
+                fd: 1,
+                syntax: Syntax::Posix
+            })
+        }
+    );
+    assert_eq!(
+        cli::parse(&args(&["--print-fd", "3", "--print-shell", "fish"])).unwrap(),
+        Command::Palette {
+            pager: false,
+            edit: false,
+            dry_run: false,
+            print: Some(Print {
+                fd: 3,
+                syntax: Syntax::Fish
+            })
+        }
+    );
+    assert!(cli::parse(&args(&["--print-shell", "fish"])).is_err());
+    assert_eq!(
+        cli::parse(&args(&["shell-init", "zsh"])).unwrap(),
+        Command::ShellInit {
+            shell: "zsh".to_owned()
+        }
+    );
+    assert_eq!(
+        cli::parse(&args(&["run", "c", "--message", "fix typo"])).unwrap(),
+        run("c", &[("message", "fix typo")])
+    );
+    assert_eq!(
+        cli::parse(&args(&["run", "commit", "--message=fix"])).unwrap(),
+        run("commit", &[("message", "fix")])
+    );
+    assert_eq!(
+        cli::parse(&args(&["list"])).unwrap(),
+        Command::List {
+            format: ListFormat::Text
+        }
+    );
+    assert_eq!(
+        cli::parse(&args(&["list", "--format", "json"])).unwrap(),
+        Command::List {
+            format: ListFormat::Json
+        }
+    );
+    assert_eq!(
+        cli::parse(&args(&["log", "--failed", "--limit", "3"])).unwrap(),
+        Command::Log {
+            filter: Filter {
+                failed: true,
+                limit: Some(3),
+                ..Default::default()
+            },
+            json: false,
+        }
+    );
+
+    for bad in [
+        &["run"][..],
+        &["run", "c", "x"],
+        &["run", "c", "--message"],
+        &["list", "x"],
+    ] {
+        let error = cli::parse(&args(bad)).unwrap_err();
+
+        assert_eq!(error.exit_code(), 64, "{:?}", bad);
+    }
+}
+
+#[test]
+fn cli_keymap_input() {
+    let keymaps = [
+        Keymap::new('c', "git commit -m {message}")
+            .with_name("commit")
+            .with_prompt("Message:")
+            .with_prompt_name("message"),
+        Keymap::new('l', "git log").with_name("c"),
+    ];
+    let commit = keymap::find(&keymaps, "commit").unwrap();
+    let message = |value: &str| [("message".to_owned(), value.to_owned())];
+
+    // Names win over keys
+    assert_eq!(keymap::find(&keymaps, "c").unwrap().key, 'l');
+    assert!(keymap::find(&keymaps, "x").is_none());
+    assert_eq!(
+        cli::keymap_input(commit, &message("fix")).unwrap(),
+        Some("fix".to_owned())
+    );
+    assert_eq!(
+        cli::keymap_input(commit, &[]).unwrap_err().to_string(),
+        "`commit` needs --message"
+    );
+    assert!(cli::keymap_input(&keymaps[1], &message("fix")).is_err());
+}
+
+#[test]
+fn cli_runs_keymaps_without_palette() {
+    let dir = std::env::temp_dir().join(format!("quicommand-state-{}", std::process::id()));
+    let quicommand = || {
+        let mut cmd = assert_cmd::Command::cargo_bin("quicommand").unwrap();
+
+        cmd.current_dir(&dir)
+            .env("XDG_STATE_HOME", &dir)
+            .env("XDG_CONFIG_HOME", &dir);
+        cmd
+    };
+
+    std::fs::create_dir_all(dir.join("quicommand")).unwrap();
+    std::fs::write(
+        dir.join("quicommand/config.toml"),
+        r#"
+[[keymap]]
+key = "c"
+name = "commit"
+cmd = "echo {message}"
+prompt = "Message:"
+prompt_name = "message"
+
+[[keymap]]
+key = "t"
+name = "count"
+cmd = "printf '%s\\n' a b c | wc -l"
+"#,
+    )
+    .unwrap();
+
+    quicommand()
+        .arg("list")
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("c  commit"));
+    quicommand()
+        .args(["run", "count"])
+        .assert()
+        .success()
+        .stdout(predicates::str::is_match(r"^\s*3\n$").unwrap());
+    quicommand()
+        .args(["run", "c", "--message", "fix typo"])
+        .assert()
+        .success()
+        .stdout("fix typo\n");
+    quicommand()
+        .args(["run", "c"])
+        .assert()
+        .code(64)
+        .stderr("quicommand: `commit` needs --message\n");
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+const CONFIG: &str = r#"
+shell = "sh -c"
+pager = true
+
+# Never offered, since `QC_NEVER_SET` isn't set
+[[keymap]]
+key = "c"
+name = "commit"
+cmd = "git commit -m {message}"
+prompt = "Commit message:"
+prompt_name = "message"
+pass_input = "env"
+timeout = 1.5
+output = "tail:10"
+when = ["exists:Cargo.toml", "env:QC_NEVER_SET"]
+"#;
+
+#[test]
+fn config_file_keymaps() {
+    let file = ConfigFile::parse("/project/.quicommand.toml", CONFIG).unwrap();
+    let keymap = &file.keymaps[0];
+
+    assert_eq!(
+        file.settings.shell,
+        Some(Shell::Command(vec!["sh".into(), "-c".into()]))
+    );
+    assert_eq!(file.settings.pager, Some(true));
+    assert_eq!(keymap.name.as_deref(), Some("commit"));
+    assert_eq!(keymap.pass_input, PassInput::Env);
+    assert_eq!(keymap.timeout, Some(Duration::from_millis(1500)));
+    assert_eq!(keymap.output, Some(Retention::Tail(10)));
+    assert_eq!(
+        keymap.conditions,
+        [
+            Condition::Exists("Cargo.toml".into()),
+            Condition::Env("QC_NEVER_SET".into())
+        ]
+    );
+    assert_eq!(
+        keymap.source.as_deref(),
+        Some("/project/.quicommand.toml".as_ref())
+    );
+    assert!(!keymap.is_available());
+    assert!(file.apply(Config::default()).pager);
+
+    let user = ConfigFile::parse(
+        "user.toml",
+        "[[keymap]]\nkey = \"c\"\ncmd = \"ls\"\n[[keymap]]\nkey = \"l\"\ncmd = \"ls\"",
+    )
+    .unwrap();
+    let keymaps = config_file::merge_keymaps(&[user, file]);
+
+    assert_eq!(keymaps.len(), 2);
+    assert_eq!(keymaps[0].cmd, "git commit -m {message}");
+}
+
+#[test]
+fn config_file_errors() {
+    for (text, message) in [
+        ("shel = \"sh\"", "unknown field `shel`"),
+        ("[[keymap]]\nkey = \"c\"", "keymap 1: `cmd` is missing"),
+        (
+            "[[keymap]]\nkey = \"cc\"\ncmd = \"ls\"",
+            "keymap 1: `cc` isn't a single key",
+        ),
+        (
+            "[[keymap]]\nkey = \"c\"\ncmd = \"ls\"\nwhen = [\"maybe\"]",
+            "keymap 1: `maybe` isn't a condition like `exists:PATH` or `env:NAME`",
+        ),
+        ("pager = \"yes\"", "`pager` has to be true or false"),
+        (
+            "[[keymap]]\nkey = \"q\"\ncmd = \"ls\"",
+            "keymap 1: `q` is used by the palette",
+        ),
+    ] {
+        let error = ConfigFile::parse("q.toml", text).unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            format!("Invalid config: q.toml: {}", message)
+        );
+    }
+
+    let dir = std::env::temp_dir().join(format!("quicommand-broken-{}", std::process::id()));
+    let quicommand = |args: &[&str]| {
+        let mut cmd = assert_cmd::Command::cargo_bin("quicommand").unwrap();
+
+        cmd.args(args)
+            .current_dir(&dir)
+            .env("XDG_STATE_HOME", &dir)
+            .env("XDG_CONFIG_HOME", &dir);
+        cmd.assert()
+    };
+
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join(config_file::PROJECT_FILE_NAME), "shel = \"sh\"").unwrap();
+
+    // Only the commands that need keymaps read the config
+    quicommand(&["list"])
+        .failure()
+        .stderr(predicates::str::contains("unknown field `shel`"));
+    quicommand(&["log"]).success();
+    quicommand(&["stats"]).success();
+    quicommand(&["shell-init", "bash"]).success();
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn list_keymaps_as_json() {
+    let dir = std::env::temp_dir().join(format!("quicommand-list-{}", std::process::id()));
+
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join(".quicommand.toml"), CONFIG).unwrap();
+
+    let output = assert_cmd::Command::cargo_bin("quicommand")
+        .unwrap()
+        .args(["list", "--format", "json"])
+        .current_dir(&dir)
+        .env("XDG_CONFIG_HOME", &dir)
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let source = dir.join(".quicommand.toml");
+
+    assert_eq!(
+        json,
+        serde_json::json!({
+            "version": 1,
+            "keymaps": [{
+                "key": "c",
+                "name": "commit",
+                "description": "git commit -m {message}",
+                "cmd": "git commit -m {message}",
+                "prompts": [{
+                    "name": "message",
+                    "message": "Commit message:",
+                    "env_var": "QC_MESSAGE",
+                    "pass_input": "env",
+                    "secret": false,
+                }],
+                "source": source,
+                "conditions": ["exists:Cargo.toml", "env:QC_NEVER_SET"],
+                "available": false,
+            }],
+        })
+    );
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+// Runs `cmd` with `shell -c`, or returns `None` when that shell isn't
+// installed
+fn run_in_shell(shell: &str, cmd: &str) -> Option<Vec<u8>> {
+    let output = std::process::Command::new(shell)
+        .args(["-c", cmd])
+        .stdin(std::process::Stdio::null())
+        .output();
+
+    match output {
+        Ok(output) => Some(output.stdout),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => panic!("{}", e),
+    }
+}
+
+#[test]
+fn printed_cmds_run_like_keymaps() {
+    let keymap = Keymap::new('e', "printf '%s\\n' \"$QC_MESSAGE\"; cat")
+        .with_prompt("Message:")
+        .with_prompt_name("message");
+    let input = "it's a \\ back\\\\slash";
+
+    for (pass_input, posix, fish) in [
+        (
+            PassInput::Cmd,
+            "printf '%s\\n' \"$QC_MESSAGE\"; cat",
+            "printf '%s\\n' \"$QC_MESSAGE\"; cat",
+        ),
+        (
+            PassInput::Env,
+            "(export QC_MESSAGE='it'\\''s a \\ back\\\\slash'; printf '%s\\n' \"$QC_MESSAGE\"; cat)",
+            "begin; set -lx QC_MESSAGE 'it\\'s a \\\\ back\\\\\\\\slash'; printf '%s\\n' \"$QC_MESSAGE\"; cat; end",
+        ),
+        (
+            PassInput::Stdin,
+            "printf '%s\\n' 'it'\\''s a \\ back\\\\slash' | { printf '%s\\n' \"$QC_MESSAGE\"; cat; }",
+            "printf '%s\\n' 'it\\'s a \\\\ back\\\\\\\\slash' | begin; printf '%s\\n' \"$QC_MESSAGE\"; cat; end",
+        ),
+    ] {
+        let keymap = keymap.clone().with_pass_input(pass_input);
+        let shell_cmd = |syntax| keymap.shell_cmd(Some(input), &Context::default(), syntax);
+
+        assert_eq!(shell_cmd(Syntax::Posix), posix);
+        assert_eq!(shell_cmd(Syntax::Fish), fish);
+
+        let mut cmd_runner = CmdRunner::new(&keymap.cmd)
+            .with_shell("sh -c".parse().unwrap())
+            .with_output_mode(OutputMode::Pipe);
+
+        cmd_runner = match pass_input {
+            PassInput::Cmd => cmd_runner,
+            PassInput::Env => cmd_runner.with_env("QC_MESSAGE", input),
+            PassInput::Stdin => cmd_runner.with_stdin(format!("{}\n", input)),
+        };
+
+        let expected = cmd_runner.run_with_output().unwrap().stdout();
+
+        assert_eq!(run_in_shell("sh", posix), Some(expected.clone()));
+
+        // fish isn't everywhere, but where it is, the widget's command has
+        // to do what the keymap does
+        match run_in_shell("fish", fish) {
+            Some(stdout) => assert_eq!(stdout, expected),
+            None => eprintln!("fish isn't installed, skipping its commands"),
+        }
+    }
+
+    assert!(quicommand::shell_init::script("bash")
+        .unwrap()
+        .contains("--print-fd 3"));
+    assert!(quicommand::shell_init::script("fish")
+        .unwrap()
+        .contains("--print-fd 3 --print-shell fish"));
+    assert!(quicommand::shell_init::script("tcsh").is_err());
+}
+
+#[test]
+fn edited_cmds() {
+    let mut step = setup_step();
+    let keymap = Keymap::new('e', "echo {}").with_prompt("Word:");
+    let input = step.input_from_prompt(
+        keymap.prompt.as_deref(),
+        vec![Ok(Key::Char('a')), Ok(Key::Char('b'))].into_iter(),
+    );
+    let keys = vec![
+        Ok(Key::Backspace),
+        Ok(Key::Backspace),
+        Ok(Key::Char('c')),
+        Ok(Key::Char('\n')),
+    ];
+    let cmd = step.edit_cmd(input, &keymap, keys.into_iter()).unwrap();
+
+    assert_eq!(cmd, Input::Text("echo c".to_owned()));
+
+    let stdout_str = String::from_utf8_lossy(&step.screen.stdout.buffer).into_owned();
+
+    assert!(stdout_str.contains("Edit the command:"));
+    assert!(stdout_str.contains("echo ab"));
+
+    let Process::Output(output) = step.process_edited(Ok(cmd), &keymap, Some("ab")).unwrap() else {
+        panic!();
+    };
+
+    assert_eq!(String::from_utf8_lossy(&output.stdout()), "c\n");
+
+    let mut step = setup_step();
+    let cmd = step.edit_cmd(Ok(Input::None), &keymap, vec![Ok(Key::Esc)].into_iter());
+
+    assert_eq!(
+        step.process_edited(cmd, &keymap, None).unwrap(),
+        Process::Exit
+    );
+
+    // The input still goes where the keymap says, and a secret one (like
+    // `{env.NAME}` values) stays out of the log in the edited command too
+    let path = std::env::temp_dir().join(format!("quicommand-edited-{}.jsonl", std::process::id()));
+    let audit_log = AuditLog::new(&path);
+    let home = std::env::var("HOME").unwrap();
+    let keymap = Keymap::new('s', "printf '%s|' \"$QC_TOKEN\" {} {env.HOME}")
+        .with_prompt("Token:")
+        .with_prompt_name("token")
+        .with_pass_input(PassInput::Env)
+        .with_secret(true);
+    let config = Config::default()
+        .with_shell("sh -c".parse().unwrap())
+        .with_audit_log(audit_log.clone());
+    let mut step = setup_step().with_config(config);
+    let keys = vec![Ok(Key::Char('!')), Ok(Key::Char('\n'))];
+    let cmd = step
+        .edit_cmd(
+            Ok(Input::Text("it's".to_owned())),
+            &keymap,
+            keys.into_iter(),
+        )
+        .unwrap();
+
+    assert_eq!(
+        cmd,
+        Input::Text(format!(
+            "printf '%s|' \"$QC_TOKEN\" 'it'\\''s' {}!",
+            quicommand::utils::shell_quote(&home)
+        ))
+    );
+
+    let Process::Output(output) = step.process_edited(Ok(cmd), &keymap, Some("it's")).unwrap()
+    else {
+        panic!();
+    };
+
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout()),
+        format!("it's|it's|{}!|", home)
+    );
+    assert_eq!(
+        audit_log.records(&Filter::default()).unwrap()[0].cmd,
+        format!("printf '%s|' \"$QC_TOKEN\" {} {}!", REDACTED, REDACTED)
+    );
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn dry_run() {
+    let cmd_runner = CmdRunner::with_cmd_type("echo \"it's\"", CmdType::Script)
+        .with_shell("bash -c".parse().unwrap())
+        .with_env("QC_WORD", "a b")
+        .with_stdin("a b\n")
+        .with_timeout(Duration::from_secs(5));
+    let description = cmd_runner.describe().unwrap();
+    let lines: Vec<&str> = description.lines().collect();
+
+    assert_eq!(
+        lines,
+        [
+            "argv[0]: bash",
+            "argv[1]: -c",
+            "argv[2]: 'echo \"it'\\''s\"'",
+            &format!("cwd: {}", std::env::current_dir().unwrap().display()),
+            "env: inherited",
+            "env: QC_WORD='a b'",
+            "stdin: \"a b\\n\"",
+            "output: captured (pty)",
+            "timeout: 5s",
+        ]
+    );
+
+    let description =
+        CmdRunner::new("echo $HOME \"${QC_NEVER_SET:-x}\" '$USER' \\$PATH $QC_WORD $1")
+            .with_env("QC_WORD", "a")
+            .describe()
+            .unwrap();
+    let env_lines: Vec<&str> = description
+        .lines()
+        .filter(|line| line.starts_with("env: "))
+        .collect();
+
+    assert_eq!(
+        env_lines,
+        [
+            "env: inherited",
+            "env: QC_WORD=a",
+            &format!(
+                "env: HOME={} (inherited)",
+                quicommand::utils::shell_quote(&std::env::var("HOME").unwrap())
+            ),
+            "env: QC_NEVER_SET (unset)",
+        ]
+    );
+
+    let config = Config::default().with_dry_run(true);
+    let mut step = setup_step().with_config(config);
+    let keymap = Keymap::new('t', "touch dry-run-file");
+
+    step.show_select_cmd(std::slice::from_ref(&keymap)).unwrap();
+
+    assert!(String::from_utf8_lossy(&step.screen.stdout.buffer).contains("Dry run"));
+    assert_eq!(
+        step.process_input(Ok(Input::None), &keymap).unwrap(),
+        Process::Exit
+    );
+    assert!(!std::path::Path::new("dry-run-file").exists());
+}
+
+#[test]
+fn input_history() {
+    let history = vec!["ls".to_owned(), "echo hi".to_owned()];
+    let mut stdout = MockStdout::new();
+    let keys = vec![
+        Ok(Key::Char('x')),
+        Ok(Key::Up),
+        Ok(Key::Up),
+        Ok(Key::Up),
+        Ok(Key::Down),
+        Ok(Key::Char('\n')),
+    ];
+
+    assert_eq!(
+        quicommand::input::input_with_history(&history, keys.into_iter(), &mut stdout).unwrap(),
+        Input::Text("echo hi".to_owned())
+    );
+
+    let keys = vec![Ok(Key::Char('x')), Ok(Key::Up), Ok(Key::Down)];
+
+    assert_eq!(
+        quicommand::input::input_with_history(&history, keys.into_iter(), &mut stdout).unwrap(),
+        Input::Text("x".to_owned())
+    );
+}
+
+#[test]
+fn ad_hoc_keymaps_are_appended() {
+    let path = std::env::temp_dir().join(format!("quicommand-append-{}.toml", std::process::id()));
+    let text = "# Our commands\nshell = \"bash -c\"  # for pipefail\n\n[[keymap]]\nkey = \"b\"\ncmd = \"make\"\n";
+
+    std::fs::write(&path, text).unwrap();
+
+    let keymap = Keymap::new('l', "ls -la | head")
+        .with_description("List")
+        .with_timeout(Duration::from_millis(1500))
+        .with_condition(Condition::Exists(".git".into()));
+
+    config_file::append_keymap(&path, &keymap).unwrap();
+
+    let written = std::fs::read_to_string(&path).unwrap();
+    let file = ConfigFile::load(&path).unwrap();
+
+    assert!(written.starts_with(text));
+    assert_eq!(file.keymaps.len(), 2);
+    assert_eq!(file.keymaps[1].cmd, "ls -la | head");
+    assert_eq!(file.keymaps[1].description, "List");
+    assert_eq!(file.keymaps[1].timeout, keymap.timeout);
+    assert_eq!(file.keymaps[1].conditions, keymap.conditions);
+
+    std::fs::remove_file(&path).unwrap();
+
+    // The palette's own keys can't be bound
+    assert!(config_file::append_keymap(&path, &Keymap::ad_hoc("ls")).is_err());
+    assert!(!path.exists());
+
+    // Built-in keymaps are kept once the file has keymaps of its own
+    let mut keymaps = vec![Keymap::new('b', "make"), Keymap::new('t', "make test")];
+
+    config_file::add_keymap(&path, &mut keymaps, Keymap::new('l', "ls")).unwrap();
+
+    let file = ConfigFile::load(&path).unwrap();
+    let keys: String = file.keymaps.iter().map(|keymap| keymap.key).collect();
+
+    assert_eq!(keys, "btl");
+    assert!(keymaps
+        .iter()
+        .all(|keymap| keymap.source.as_deref() == Some(path.as_path())));
+
+    config_file::add_keymap(&path, &mut keymaps, Keymap::new('m', "man ls")).unwrap();
+
+    assert_eq!(ConfigFile::load(&path).unwrap().keymaps.len(), 4);
+
+    std::fs::write(&path, "keymap = 1\n").unwrap();
+
+    assert!(config_file::append_keymap(&path, &keymap).is_err());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "keymap = 1\n");
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn keymaps_are_edited_in_place() {
+    let path = std::env::temp_dir().join(format!("quicommand-edit-{}.toml", std::process::id()));
+    let text = "\
+shell = \"bash -c\"
+
+# Builds everything
+[[keymap]]
+key = \"b\"
+description = \"Build\" # shown in the menu
+cmd = \"make\"
+timeout = 60
+
+# Runs the tests
+[[keymap]]
+key = \"t\"
+cmd = \"make test\"
+";
+
+    std::fs::write(&path, text).unwrap();
+
+    let build = ConfigFile::load(&path).unwrap().keymaps[0]
+        .clone()
+        .with_description("Build all")
+        .with_prompt("Target:");
+
+    config_file::update_keymap(&path, 'b', &build).unwrap();
+
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        text.replace("\"Build\" #", "\"Build all\" #")
+            .replace("timeout = 60\n", "timeout = 60\nprompt = \"Target:\"\n")
+    );
+
+    config_file::swap_keymaps(&path, 'b', 't').unwrap();
+
+    let swapped = std::fs::read_to_string(&path).unwrap();
+
+    assert!(swapped.starts_with(
+        "shell = \"bash -c\"\n\n# Runs the tests\n[[keymap]]\nkey = \"t\"\ncmd = \"make test\"\n\n# Builds everything\n"
+    ));
+
+    std::fs::write(
+        &path,
+        "[[keymap]]\nkey = \"a\"\ncmd = \"a\"\n\n# B\n[[keymap]]\nkey = \"b\"\ncmd = \"b\"\n",
+    )
+    .unwrap();
+    config_file::swap_keymaps(&path, 'a', 'b').unwrap();
+
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        "# B\n[[keymap]]\nkey = \"b\"\ncmd = \"b\"\n\n[[keymap]]\nkey = \"a\"\ncmd = \"a\"\n"
+    );
+    std::fs::write(&path, swapped).unwrap();
+    config_file::remove_keymap(&path, 't').unwrap();
+
+    let file = ConfigFile::load(&path).unwrap();
+
+    assert_eq!(file.keymaps.len(), 1);
+    assert_eq!(file.keymaps[0].description, "Build all");
+    assert!(config_file::remove_keymap(&path, 'x').is_err());
+    assert!(config_file::update_keymap(&path, 'b', &build.clone().with_shell(Shell::None)).is_ok());
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn keymap_editor() {
+    let dir = std::env::temp_dir().join(format!("quicommand-editor-{}", std::process::id()));
+    let path = dir.join(config_file::PROJECT_FILE_NAME);
+
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        &path,
+        "[[keymap]]\nkey = \"b\"\ncmd = \"make\"\n\n[[keymap]]\nkey = \"t\"\ncmd = \"make test\"\n",
+    )
+    .unwrap();
+
+    let mut keymaps = ConfigFile::load(&path).unwrap().keymaps;
+
+    keymaps.push(Keymap::new('x', "built in"));
+    keymaps
+        .push(Keymap::new('h', "hidden").with_condition(Condition::Env("QC_NEVER_SET".to_owned())));
+
+    let mut editor = KeymapEditor::new(&mut keymaps, &path, (80, 24));
+    let mut stdout = MockStdout::new();
+
+    editor.render(&mut stdout).unwrap();
+
+    assert!(
+        String::from_utf8_lossy(&stdout.buffer).contains("h  hidden  (built in, not offered here)")
+    );
+    assert!(editor.save(Keymap::new('h', "make"), false).is_err());
+
+    assert_eq!(editor.key(Key::Char('j')), None);
+    assert_eq!(editor.key(Key::Char('K')), Some(Action::MoveUp));
+    assert_eq!(editor.key(Key::Char('q')), Some(Action::Close));
+
+    editor.move_selected(true).unwrap();
+
+    assert_eq!(editor.selected(), 0);
+    assert!(editor
+        .save(Keymap::new('b', "make").with_description("Build"), false)
+        .is_err());
+    assert!(editor.save(Keymap::new(':', "make"), false).is_err());
+
+    editor
+        .save(
+            Keymap::new('c', "make clean").with_description("Clean"),
+            false,
+        )
+        .unwrap();
+
+    assert_eq!(editor.selected(), 4);
+    assert!(editor.move_selected(true).is_err());
+
+    editor.key(Key::Char('k'));
+    editor.key(Key::Char('k'));
+
+    assert!(editor.delete().is_err());
+
+    editor.key(Key::Char('k'));
+    editor.key(Key::Char('k'));
+    editor
+        .save(Keymap::new('T', "cargo test").with_source(&path), true)
+        .unwrap();
+    editor.key(Key::Char('j'));
+    editor.delete().unwrap();
+
+    let file = ConfigFile::load(&path).unwrap();
+    let keys: Vec<char> = file.keymaps.iter().map(|k| k.key).collect();
+
+    assert_eq!(keys, ['T', 'c']);
+    assert_eq!(file.keymaps[0].cmd, "cargo test");
+    assert_eq!(
+        keymaps.iter().map(|k| k.key).collect::<Vec<_>>(),
+        ['T', 'x', 'h', 'c']
+    );
+
+    // Drawn for the terminal's size at the time, not the one it started with
+    let mut editor = KeymapEditor::new(&mut keymaps, &path, (80, 24));
+    let mut stdout = MockStdout::new();
+
+    editor
+        .run(&mut stdout, vec![Ok(Key::Char('q'))].into_iter(), || {
+            (12, 5)
+        })
+        .unwrap();
+
+    let screen = String::from_utf8_lossy(&stdout.buffer);
+
+    assert!(screen.contains("T  cargo tes"));
+    assert!(!screen.contains("T  cargo test"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+fn record(keymap: &Keymap, start: DateTime<Local>, secs: i64, exit_code: i32) -> Record {
+    Record {
+        cmd: keymap.cmd.clone(),
+        key: keymap.key,
+        description: keymap.description.clone(),
+        cwd: "/project".into(),
+        start,
+        end: start + chrono::Duration::seconds(secs),
+        termination: Termination::Exited,
+        exit_code,
+        output_sha256: String::new(),
+        input: None,
+    }
+}
+
+#[test]
+fn keymap_order() {
+    let keymaps = vec![
+        Keymap::new('b', "make").with_description("Build"),
+        Keymap::new('t', "make test").with_description("test"),
+        Keymap::new('a', "make all").with_description("All"),
+    ];
+    let now = Local::now();
+    let days = |n| now - chrono::Duration::days(n);
+    // `t` twice long ago is still less than `a` once today
+    let records = [
+        record(&keymaps[1], days(100), 1, 0),
+        record(&keymaps[1], days(40), 1, 0),
+        record(&keymaps[2], days(0), 1, 0),
+        // Same key, but a different keymap
+        record(&Keymap::new('b', "cargo build"), days(0), 1, 0),
+    ];
+    let frecency = Frecency::new(&records, now);
+    let sorted = |order: Order| {
+        let mut keymaps = keymaps.clone();
+
+        keymap::sort(&mut keymaps, order, |keymap| frecency.score(keymap));
+        keymaps.iter().map(|keymap| keymap.key).collect::<String>()
+    };
+
+    assert_eq!(sorted(Order::Defined), "bta");
+    assert_eq!(sorted(Order::Alphabetical), "abt");
+    assert_eq!(sorted(Order::Frecency), "atb");
+    assert_eq!(frecency.score(&keymaps[1]), 40.0);
+    assert_eq!(frecency.score(&Keymap::new('x', "never run")), 0.0);
+    assert_eq!("frecency".parse::<Order>().unwrap(), Order::Frecency);
+    assert!("random".parse::<Order>().is_err());
+
+    let file = ConfigFile::parse("c.toml", "order = \"alphabetical\"").unwrap();
+
+    assert_eq!(file.apply(Config::default()).order, Order::Alphabetical);
+}
+
+#[test]
+fn stats() {
+    let commit = Keymap::new('c', "git commit -m {}").with_description("Commit");
+    let build = Keymap::new('b', "make");
+    let unused = Keymap::new('u', "true").with_source("/project/.quicommand.toml");
+    let now = Local::now();
+    let with_input = |mut record: Record, input: &str| {
+        record.input = Some(input.to_owned());
+        record
+    };
+    let mut records: Vec<Record> = (1..=20)
+        .map(|secs| record(&build, now, secs, i32::from(secs > 15)))
+        .collect();
+
+    records.push(with_input(record(&commit, now, 2, 0), "wip"));
+    records.push(with_input(record(&commit, now, 1, 0), "fix"));
+    records.push(with_input(record(&commit, now, 3, 0), "wip"));
+
+    let stats = Stats::new(&records, &[commit, build, unused]);
+
+    assert_eq!(stats.keymaps.len(), 2);
+    assert_eq!(stats.keymaps[0].key, 'b');
+    assert_eq!(stats.keymaps[0].runs, 20);
+    assert_eq!(stats.keymaps[0].success_rate(), 0.75);
+    assert_eq!(stats.keymaps[0].median, Duration::from_secs(10));
+    assert_eq!(stats.keymaps[0].p95, Duration::from_secs(19));
+    assert_eq!(
+        stats.keymaps[1].top_inputs,
+        [("wip".to_owned(), 2), ("fix".to_owned(), 1)]
+    );
+    assert_eq!(stats.keymaps[1].median, Duration::from_secs(2));
+    assert_eq!(stats.unused.len(), 1);
+    assert_eq!(stats.unused[0].key, 'u');
+
+    let text = stats.to_string();
+
+    let lines: Vec<&str> = text.lines().collect();
+
+    assert_eq!(lines[0], "key   runs  success  median     p95  description");
+    assert_eq!(lines[1], "b       20      75%   10.0s   19.0s  make");
+    assert!(text.contains("inputs: \"wip\" (2), \"fix\" (1)"));
+    assert!(text.contains("Never used:\nu  true  (/project/.quicommand.toml)"));
+    assert_eq!(cli::parse(&["stats".to_owned()]).unwrap(), Command::Stats);
+}
+
+#[test]
+fn built_in_placeholders() {
+    let context = Context::default().with_last_output(&pager_output());
+    let render = |cmd: &str, input: Option<&str>| {
+        Keymap::new('t', cmd)
+            .with_prompt_name("date")
+            .render_cmd(input, &context)
+    };
+
+    assert_eq!(
+        render("echo \"{last_output}\"", None),
+        "echo \"one\ntwo\nthree\nfive\n100%\nsix\""
+    );
+    assert_eq!(render("echo \"{env.QC_NEVER_SET}\"", None), "echo \"\"");
+    assert_eq!(render("echo {} {unknown}", None), "echo {} {unknown}");
+    // The prompt's name wins
+    assert_eq!(render("echo {date}", Some("today")), "echo today");
+    assert_eq!(render("{date}", None).len(), "2024-05-01".len());
+    assert_eq!(render("{time}", None).len(), "14:03:59".len());
+    assert_eq!(
+        render("{cwd|raw}", None),
+        std::env::current_dir().unwrap().display().to_string()
+    );
+    assert_eq!(
+        context.lookup("project_root"),
+        Some(env!("CARGO_MANIFEST_DIR").to_owned())
+    );
+
+    let dir = std::env::temp_dir().join(format!("quicommand-git-{}", std::process::id()));
+    let git = |args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .args(args)
+            .current_dir(&dir)
+            .stdout(std::process::Stdio::null())
+            .status()
+            .unwrap();
+
+        assert!(status.success());
+    };
+
+    std::fs::create_dir_all(dir.join("sub")).unwrap();
+    git(&["init", "-q", "-b", "feature"]);
+    git(&["remote", "add", "upstream", "https://example.com/repo.git"]);
+
+    assert_eq!(quicommand::context::project_root(&dir.join("sub")), dir);
+
+    std::fs::write(
+        dir.join(config_file::PROJECT_FILE_NAME),
+        "[[keymap]]\nkey = \"p\"\ncmd = \"echo {git.remote} {git.branch} {env.QC_PLACEHOLDER_TEST}\"\n",
+    )
+    .unwrap();
+
+    assert_cmd::Command::cargo_bin("quicommand")
+        .unwrap()
+        .args(["run", "p"])
+        .current_dir(&dir)
+        .env("XDG_STATE_HOME", &dir)
+        .env("XDG_CONFIG_HOME", &dir)
+        .env("QC_PLACEHOLDER_TEST", "a  b")
+        .assert()
+        .success()
+        .stdout("upstream feature a  b\n");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn cmd_output_tail() {
+    let mut cmd_runner = CmdRunner::new("seq 1 1000")
+        .with_output_mode(OutputMode::Pipe)
+        .with_retention("tail:3".parse().unwrap());
+    let output = cmd_runner.run_with_output().unwrap();
+
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout()),
+        "998\n999\n1000\n"
+    );
+    assert_eq!(output.spill_file, None);
+    assert!("tail:x".parse::<Retention>().is_err());
+}
+
+#[test]
+fn cmd_output_spills_to_file() {
+    let mut cmd_runner = CmdRunner::new("seq 1 1000")
+        .with_output_mode(OutputMode::Pipe)
+        .with_memory_cap(100);
+    let output = cmd_runner.run_with_output().unwrap();
+    let spill_file = output.spill_file.clone().unwrap();
+    let expected: String = (1..=1000).map(|n| format!("{}\n", n)).collect();
+
+    // `combined` is the only copy kept in memory
+    assert!(output.combined.iter().map(|c| c.bytes.len()).sum::<usize>() <= 100);
+    assert!(output.stdout().len() <= 100);
+    assert!(expected.as_bytes().ends_with(&output.stdout()));
+    assert_eq!(std::fs::read_to_string(&spill_file).unwrap(), expected);
+
+    drop(output);
+
+    assert!(!spill_file.exists());
+
+    let file = ConfigFile::parse("c.toml", "output_memory_cap = 100").unwrap();
+
+    assert_eq!(file.apply(Config::default()).output_memory_cap, 100);
+    assert!(ConfigFile::parse("c.toml", "output_memory_cap = -1").is_err());
+}